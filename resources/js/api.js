@@ -1,6 +1,13 @@
 const DEFAULT_WEB_SOCKET_DEBUG = false
 const DEFAULT_WEB_SOCKET_RECONNECT_INTERVAL = 2000
 const DEFAULT_WEB_SOCKET_ADDR = 'ws://127.0.0.1:57000/'
+
+/// 購読設定にチャンネルの条件が含まれるか (サーバー側の WsServerPayloadSubscription::has_channel_conditions と同じ)
+function has_channel_conditions(subscription)
+{
+ return (subscription.channels || []).length + (subscription.flags || []).length + (subscription.without_flags || []).length > 0
+}
+
 export default class VacApi
 {
  /// arg:                  default:                 note:
@@ -99,6 +106,17 @@ export default class VacApi
    this.register_ws_open_event(ws_open_event)
   this.ws.onopen = e =>
   {
+   // 再接続した場合もサーバー側の購読設定は失われているので送り直す
+   // 一度も購読していない場合は何も送らず全ての内容が流れ、全て解除した場合は何も流れない状態にする
+   if (this.ws_subscriptions)
+   {
+    for (let subscription of this.ws_subscriptions)
+     this.ws_send({ subscribe: subscription })
+    if (this.ws_subscriptions.length === 0)
+     this.ws_send({ unsubscribe: {} })
+   }
+   if (this.ws_audio_queue_subscribed)
+    this.ws_send({ subscribe: { audio_queue: true } })
    for (let ws_open_event of this.ws_open_events)
     ws_open_event(e)
   }
@@ -122,6 +140,49 @@ export default class VacApi
 
  ws_send(payload) { this.ws.send(JSON.stringify(payload)) }
 
+ /// WebSocket で受信したいチャンネルやフラグを購読します。一度も購読していない場合は全ての内容が流れます。
+ ///  例: vac.api.ws_subscribe({ channels: ['ai', 'user-*'], flags: ['is_final'], without_flags: [] })
+ /// null: 一度も購読していない(全て流れる) / 配列: 何れかの購読にマッチした内容だけ流れる(空なら何も流れない)
+ ws_subscriptions = null
+ /// 再生キューの状態を購読しているか。サーバー側と同じくチャンネルの購読とは独立して扱う
+ ws_audio_queue_subscribed = false
+ ws_subscribe(subscription)
+ {
+  if (subscription.audio_queue)
+   this.ws_audio_queue_subscribed = true
+  if (!subscription.audio_queue || has_channel_conditions(subscription))
+   (this.ws_subscriptions ??= []).push({ ...subscription, audio_queue: false })
+  if (this.ws?.readyState === WebSocket.OPEN)
+   this.ws_send({ subscribe: subscription })
+ }
+
+ /// 購読を解除します。 channels を省略すると全ての購読を解除します。
+ /// 購読が全て無くなると何も流れなくなります。(再接続した場合も同じです)
+ ws_unsubscribe(subscription = {})
+ {
+  if (subscription.audio_queue)
+   this.ws_audio_queue_subscribed = false
+  // サーバー側と同じく audio_queue だけの解除ではチャンネルの購読は変えない
+  if (!subscription.audio_queue || has_channel_conditions(subscription))
+  {
+   let channels = subscription.channels || []
+   if (channels.length === 0)
+    this.ws_subscriptions = []
+   else if (this.ws_subscriptions)
+   {
+    // チャンネルが全て解除された購読は取り除く(残すと全チャンネルが対象になってしまう)。元から全チャンネル対象の購読は残す
+    this.ws_subscriptions = this.ws_subscriptions.filter(s =>
+    {
+     let had_channels = (s.channels || []).length > 0
+     s.channels = (s.channels || []).filter(c => !channels.includes(c))
+     return !(had_channels && s.channels.length === 0)
+    })
+   }
+  }
+  if (this.ws?.readyState === WebSocket.OPEN)
+   this.ws_send({ unsubscribe: subscription })
+ }
+
  stop_ws() { this.ws.close() }
 
  init_rest_input(rest_input_event, rest_input_url, rest_input_method)
@@ -21,6 +21,8 @@ export default class VacOutput
  request_conf = new Map()
  /// VAC から取得したデータのキャッシュ
  data_cache = null
+ /// 音声を順番に再生するための Promise の連鎖
+ audio_chain = Promise.resolve()
 
  /// run の継続フラグ
  /// note: 通常はユーザーが直接操作する必要はないが、必要に応じて false にすると run が終了する
@@ -134,10 +136,36 @@ export default class VacOutput
    element.innerText = text
   }
 
+  // data-vac-output-audio を持つ要素がある場合は metadata.audio_url の音声を再生する
+  if (datum.flags.includes('is_final') && [...conf.elements].some(element => 'vacOutputAudio' in element.dataset))
+   this.play_audio(datum)
+
   if (conf)
    conf.retrieved_id = Math.max(conf.retrieved_id, datum.id)
  }
 
+ /// datum.metadata.audio_url の音声を前の音声が終わってから再生する
+ /// note: VAC の Processor (coeiroink, os-tts) に channel_to を設定すると合成した音声の audio_url 付きの内容が届きます
+ play_audio(datum)
+ {
+  let audio_url = datum.metadata && datum.metadata.audio_url
+  if (!audio_url)
+   return
+  // api_url と同じホストから取得する
+  let base = new URL(this.api_url.replace(/^ws(s?):\/\//, 'http$1://'), location.href)
+  let audio = new Audio(new URL(audio_url, base).href)
+  this.audio_chain = this.audio_chain.then(() => new Promise(resolve =>
+  {
+   audio.onended = resolve
+   audio.onerror = resolve
+   audio.play().catch(e =>
+   {
+    console.error(`音声を再生できませんでした: ${e}`)
+    resolve()
+   })
+  }))
+ }
+
  /// 要素の内容を更新する
  /// note: 通常は run を使えば楽なのでユーザーは凝ったことをしない限り使う必要はない
  update_elements()
@@ -225,4 +253,4 @@ window.VacOutput = VacOutput
 if (!window.vac)
  window.vac = {}
 window.vac.output = new VacOutput()
-console.log('VacOutput loaded')
\ No newline at end of file
+console.log('VacOutput loaded')
\ No newline at end of file
@@ -1,6 +1,8 @@
 mod sm_with_conf;
+mod sm_with_state;
 mod sm_without_conf;
 
+use crate::{DEFAULT_COEIROINK_API_URL, DEFAULT_VOICEVOX_API_URL};
 use clap::Parser;
 
 const DEFAULT_CONF_PATH: &str = "conf.toml";
@@ -17,8 +19,14 @@ pub struct Args {
  pub debug: bool,
 
  /// CoeiroInk の Speakers を表示します。使用可能な Speakers の一覧を確認する用途で使用できます。
- #[arg(long)]
- pub coeiroink_speakers: bool,
+ /// API の URL を続けて指定できます。指定しない場合は CoeiroInk の既定の http://localhost:50032/v1/predict を使います。
+ #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_COEIROINK_API_URL)]
+ pub coeiroink_speakers: Option<String>,
+
+ /// VOICEVOX 互換のエンジン (VOICEVOX, SHAREVOX, AivisSpeech など) の Speakers を表示します。
+ /// API の URL を続けて指定できます。指定しない場合は VOICEVOX の既定の http://127.0.0.1:50021 を使います。
+ #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_VOICEVOX_API_URL)]
+ pub voicevox_speakers: Option<String>,
 
  /// OS-TTSのテストを実行します。使用可能なOS-TTSの一覧を確認する用途でも使用できます。
  #[arg(long)]
@@ -40,6 +48,28 @@ pub struct Args {
  #[arg(long)]
  pub openai_api_clear_files: bool,
 
+ /// 保存された channel_data (.ron または .jsonl) を再生します。
+ /// 記録された入力を元の時間間隔で再入力するので、辞書やプロンプト、オーバーレイの調整を配信せずに試せます。
+ /// 再生中は state_data_path への保存と読み込みは行われません。
+ #[arg(long)]
+ pub replay: Option<String>,
+
+ /// この引数は --replay と併用する引数です。
+ /// 再生するチャンネルをカンマ区切りで指定できます。指定しない場合は Processor を経由せずに入力されたデータだけを再生します。
+ #[arg(long, value_delimiter = ',')]
+ pub replay_channels: Vec<String>,
+
+ /// この引数は --replay と併用する引数です。
+ /// 再生速度の倍率を指定します。 0 を指定すると待たずに次々と再生します。
+ #[arg(long, default_value_t = 1.0)]
+ pub replay_speed: f64,
+
+ /// 1 行に 1 つのフレーズを書いたテキストファイルを指定し、音声合成して音声のキャッシュ (audio_cache_path) へ保存します。
+ /// よく使う挨拶などを事前にキャッシュしておくと配信中の読み上げの待ち時間を減らせます。
+ /// --processor-id で音声合成に使うプロセッサーを指定できます。指定しない場合は全ての音声合成のプロセッサーで行います。
+ #[arg(long)]
+ pub prewarm_audio_cache: Option<String>,
+
  /// 実験的な機能を有効にします。主に開発用で、動作内容は何かと開発者の都合にあわせて変化します。
  #[arg(long)]
  pub experimental: bool,
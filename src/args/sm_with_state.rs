@@ -0,0 +1,58 @@
+use super::Args;
+use crate::SharedState;
+use anyhow::Result;
+
+impl Args {
+ /// State が必要な特殊モード処理群の実行
+ /// 通常の動作モードと並行して動作させるため、処理は tokio::spawn で切り離して実行します。
+ pub fn execute_special_modes_with_state(&self, state: &SharedState) {
+  if let Some(replay) = self.replay.clone() {
+   let state = state.clone();
+   let channels = self.replay_channels.clone();
+   let speed = self.replay_speed;
+   tokio::spawn(async move {
+    if let Err(e) = crate::state::replay(state, &replay, &channels, speed).await {
+     log::error!("{:?} の再生に失敗しました: {}", replay, e);
+    }
+   });
+  }
+
+  if let Some(path) = self.prewarm_audio_cache.clone() {
+   let state = state.clone();
+   let processor_id = self.processor_id.clone();
+   tokio::spawn(async move {
+    match prewarm_audio_cache(&state, &path, processor_id.as_deref()).await {
+     Ok(count) => log::info!("{:?} のフレーズから {} 件の音声をキャッシュへ保存しました。", path, count),
+     Err(e) => log::error!("{:?} による音声のキャッシュの事前生成に失敗しました: {}", path, e),
+    }
+   });
+  }
+ }
+}
+
+/// path の各行のフレーズを processor_id のプロセッサー (None の場合は全てのプロセッサー) で音声合成してキャッシュへ保存します。
+async fn prewarm_audio_cache(state: &SharedState, path: &str, processor_id: Option<&str>) -> Result<usize> {
+ if state.read().await.audio_cache.is_none() {
+  anyhow::bail!("audio_cache_path が設定されていないため音声のキャッシュは無効です。");
+ }
+
+ let phrases = tokio::fs::read_to_string(path).await?;
+ let processors = state.read().await.processors.clone();
+
+ let mut count = 0;
+ for p in processors {
+  if let Some(processor_id) = processor_id {
+   if p.get_id().await != processor_id {
+    continue;
+   }
+  }
+  for phrase in phrases.lines().map(str::trim).filter(|l| !l.is_empty()) {
+   match p.prewarm_audio_cache(phrase).await {
+    Ok(n) => count += n,
+    Err(e) => log::warn!("{:?} の音声合成に失敗しました: {:?} {:?}", phrase, p.get_id().await, e),
+   }
+  }
+ }
+
+ Ok(count)
+}
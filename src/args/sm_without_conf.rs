@@ -1,10 +1,10 @@
 use super::Args;
-use crate::SharedAudioSink;
+use crate::{AudioBackend, AudioOutput};
 use anyhow::Result;
 
 impl Args {
  /// conf 不要の特殊モード処理群の実行
- pub async fn execute_special_modes_without_conf(&self, audio_sink: SharedAudioSink) -> Result<()> {
+ pub async fn execute_special_modes_without_conf(&self) -> Result<()> {
   // debug が true ならログレベルを Trace に設定
   match self.debug {
    true => {
@@ -15,9 +15,12 @@ impl Args {
    false => log::set_max_level(log::LevelFilter::Info),
   }
 
-  if self.coeiroink_speakers {
-   log::info!("CoeiroInk の Speakers を表示します。CoeiroInkのAPIが動作していない場合はエラーが発生します。");
-   let speakers = match crate::processor::CoeiroInk::get_speakers().await {
+  if let Some(api_url) = self.coeiroink_speakers.as_ref() {
+   log::info!(
+    "CoeiroInk の Speakers を表示します。CoeiroInkのAPIが動作していない場合はエラーが発生します。 api_url = {}",
+    api_url
+   );
+   let speakers = match crate::processor::CoeiroInk::get_speakers(api_url).await {
     Ok(speakers) => speakers,
     Err(e) => {
      log::error!("CoeiroInk の Speakers の取得に失敗しました: {}", e);
@@ -35,8 +38,33 @@ impl Args {
    std::process::exit(0);
   }
 
+  if let Some(api_url) = self.voicevox_speakers.as_ref() {
+   log::info!(
+    "VOICEVOX の Speakers を表示します。VOICEVOX のAPIが動作していない場合はエラーが発生します。 api_url = {}",
+    api_url
+   );
+   let speakers = match crate::processor::Voicevox::get_speakers(api_url).await {
+    Ok(speakers) => speakers,
+    Err(e) => {
+     log::error!("VOICEVOX の Speakers の取得に失敗しました: {}", e);
+     std::process::exit(1);
+    },
+   };
+   println!("--- VOICEVOX Speakers ---");
+   for speaker in speakers {
+    println!(" * name/speaker_uuid: {} / {}", speaker.name, speaker.speaker_uuid);
+    for style in speaker.styles {
+     println!("  - styleName/style_id: {} / {}", style.name, style.id);
+    }
+   }
+
+   std::process::exit(0);
+  }
+
   if self.test_os_tts {
-   if let Err(e) = crate::processor::OsTts::test(audio_sink).await {
+   // conf を読み込む前のため OS の既定の音声出力デバイスを使う
+   let audio_output = AudioOutput::open(&AudioBackend::Default)?;
+   if let Err(e) = crate::processor::OsTts::test(audio_output.audio_sink.clone()).await {
     log::error!("OS-TTS のテストに失敗しました: {}", e);
     std::process::exit(1);
    }
@@ -0,0 +1,115 @@
+use crate::{Arc, Mutex};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub const DEFAULT_AUDIO_CACHE_MAX_SIZE_IN_MB: u64 = 512;
+/// キャッシュのファイルの拡張子。中身は WAV や MP3 などエンジンが返したままの音声データです。
+const AUDIO_CACHE_FILE_EXTENSION: &str = "audio";
+
+/// 合成した音声をエンジン、声の設定、テキストから作ったキーでディスクに保存して再利用するキャッシュです。
+/// 合計サイズが上限を超えると最後に使われた日時が古いものから削除します。
+#[derive(Debug, Clone)]
+pub struct AudioCache {
+ dir: PathBuf,
+ max_size_in_bytes: u64,
+ // 書き込みと削除を直列化
+ lock: Arc<Mutex<()>>,
+}
+
+impl AudioCache {
+ pub fn new(dir: impl Into<PathBuf>, max_size_in_mb: u64) -> Self {
+  Self {
+   dir: dir.into(),
+   max_size_in_bytes: max_size_in_mb.saturating_mul(1024 * 1024),
+   lock: Arc::new(Mutex::new(())),
+  }
+ }
+
+ /// engine (feature), voice (声の設定), text から SHA-256 の 16 進数文字列のキーを作ります。
+ pub fn key(engine: &str, voice: &str, text: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(engine.as_bytes());
+  hasher.update([0]);
+  hasher.update(voice.as_bytes());
+  hasher.update([0]);
+  hasher.update(Self::normalize_text(text).as_bytes());
+  format!("{:x}", hasher.finalize())
+ }
+
+ /// 前後の空白を除き、連続する空白を 1 つにまとめます。
+ pub fn normalize_text(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<_>>().join(" ")
+ }
+
+ fn path(&self, key: &str) -> PathBuf {
+  self.dir.join(format!("{}.{}", key, AUDIO_CACHE_FILE_EXTENSION))
+ }
+
+ pub async fn contains(&self, key: &str) -> bool {
+  tokio::fs::try_exists(self.path(key)).await.unwrap_or(false)
+ }
+
+ /// キャッシュされた音声データを取得します。
+ pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+  let path = self.path(key);
+  let data = tokio::fs::read(&path).await.ok()?;
+  // 最近使ったものが削除されにくいよう更新日時を更新
+  if let Err(e) = touch(path).await {
+   log::trace!("音声のキャッシュの更新日時を更新できませんでした: {:?}", e);
+  }
+  Some(data)
+ }
+
+ /// 音声データをキャッシュへ保存し、上限を超えていれば古いものを削除します。
+ pub async fn insert(&self, key: &str, data: &[u8]) -> Result<()> {
+  let _lock = self.lock.lock().await;
+
+  tokio::fs::create_dir_all(&self.dir).await?;
+  let path = self.path(key);
+  // 書きかけのファイルを読まないよう一時ファイルに書いてから置き換え
+  let temp = path.with_extension("tmp");
+  tokio::fs::write(&temp, data).await?;
+  tokio::fs::rename(&temp, &path).await?;
+  log::trace!("音声をキャッシュへ保存しました: {}", path.display());
+
+  self.evict().await
+ }
+
+ /// 合計サイズが上限を超えていたら更新日時が古いものから削除します。
+ async fn evict(&self) -> Result<()> {
+  let mut entries = vec![];
+  let mut total = 0;
+  let mut dir = tokio::fs::read_dir(&self.dir).await?;
+  while let Some(entry) = dir.next_entry().await? {
+   let path = entry.path();
+   if path.extension().and_then(|e| e.to_str()) != Some(AUDIO_CACHE_FILE_EXTENSION) {
+    continue;
+   }
+   let metadata = entry.metadata().await?;
+   total += metadata.len();
+   entries.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len(), path));
+  }
+
+  if total <= self.max_size_in_bytes {
+   return Ok(());
+  }
+
+  entries.sort_by_key(|(modified, _, _)| *modified);
+  for (_, len, path) in entries {
+   if total <= self.max_size_in_bytes {
+    break;
+   }
+   tokio::fs::remove_file(&path).await?;
+   total -= len;
+   log::debug!("音声のキャッシュが上限を超えたため削除しました: {}", path.display());
+  }
+
+  Ok(())
+ }
+}
+
+async fn touch(path: PathBuf) -> std::io::Result<()> {
+ tokio::task::spawn_blocking(move || std::fs::OpenOptions::new().write(true).open(path)?.set_modified(SystemTime::now())).await?
+}
@@ -0,0 +1,75 @@
+use crate::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_AUDIO_CLIP_TTL_IN_SECS: u64 = 300;
+/// 保持する音声の数の上限
+const AUDIO_CLIP_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+struct AudioClip {
+ id: u64,
+ mime: String,
+ data: Arc<Vec<u8>>,
+ created_at: Instant,
+}
+
+/// 合成した音声をブラウザーから /audio/{id} で取得できるよう一時的に保持します。
+/// VAC とは別の PC の OBS のブラウザーソースなどで字幕と一緒に音声を再生するために使います。
+#[derive(Debug, Clone)]
+pub struct AudioClips {
+ clips: Arc<Mutex<VecDeque<AudioClip>>>,
+ ttl: Duration,
+ last_id: Arc<Mutex<u64>>,
+}
+
+impl AudioClips {
+ pub fn new(ttl_in_secs: u64) -> Self {
+  Self {
+   clips: Arc::new(Mutex::new(VecDeque::new())),
+   ttl: Duration::from_secs(ttl_in_secs),
+   last_id: Arc::new(Mutex::new(0)),
+  }
+ }
+
+ /// 音声を保持し、取得用の URL を返します。
+ pub async fn insert(&self, data: Vec<u8>, mime: &str) -> String {
+  let id = {
+   let mut last_id = self.last_id.lock().await;
+   *last_id += 1;
+   *last_id
+  };
+
+  let mut clips = self.clips.lock().await;
+  self.remove_expired(&mut clips);
+  while clips.len() >= AUDIO_CLIP_CAPACITY {
+   clips.pop_front();
+  }
+  clips.push_back(AudioClip {
+   id,
+   mime: mime.to_string(),
+   data: Arc::new(data),
+   created_at: Instant::now(),
+  });
+  log::trace!("音声 {} を /audio/{} で取得できるようにしました。", id, id);
+
+  Self::url(id)
+ }
+
+ /// id の音声の MIME と内容を返します。期限切れなどで見つからない場合は None を返します。
+ pub async fn get(&self, id: u64) -> Option<(String, Arc<Vec<u8>>)> {
+  let mut clips = self.clips.lock().await;
+  self.remove_expired(&mut clips);
+  clips.iter().find(|c| c.id == id).map(|c| (c.mime.clone(), c.data.clone()))
+ }
+
+ pub fn url(id: u64) -> String {
+  format!("/audio/{}", id)
+ }
+
+ fn remove_expired(&self, clips: &mut VecDeque<AudioClip>) {
+  while clips.front().is_some_and(|c| c.created_at.elapsed() > self.ttl) {
+   clips.pop_front();
+  }
+ }
+}
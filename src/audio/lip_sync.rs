@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use rodio::buffer::SamplesBuffer;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_LIP_SYNC_FPS: u32 = 30;
+/// 正規化した後の音量がこれより小さいフレームは口を閉じる
+const LIP_SYNC_NOISE_GATE: f32 = 0.05;
+
+/// アバターの口パク用の音量の推移です。 lip_sync_channel へ JSON で送出されます。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LipSyncTrack {
+ /// 音声の再生を開始した日時。 amplitudes[0] はこの時点のフレームです。
+ pub started_at: Option<DateTime<Utc>>,
+ /// 再生キューの中での音声の ID
+ pub audio_queue_id: Option<u64>,
+ /// 読み上げる内容
+ pub content: String,
+ /// 1 秒あたりのフレーム数
+ pub fps: u32,
+ pub duration_in_ms: u64,
+ /// フレームごとの音量。音声の中で最も大きいフレームを 1.0 として 0.0 ～ 1.0 に正規化しています。
+ pub amplitudes: Vec<f32>,
+}
+
+impl LipSyncTrack {
+ /// source を全てメモリー上に展開してフレームごとの音量を求め、再生用の音声と一緒に返します。
+ pub fn analyze<S: Source>(source: S, fps: u32, content: &str) -> (SamplesBuffer, Self) {
+  let channels = source.channels();
+  let sample_rate = source.sample_rate();
+  let samples = source.collect::<Vec<f32>>();
+
+  let fps = fps.max(1);
+  let frame_len = ((sample_rate as usize * channels as usize) / fps as usize).max(1);
+  let rms = samples
+   .chunks(frame_len)
+   .map(|frame| (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt())
+   .collect::<Vec<_>>();
+
+  let peak = rms.iter().cloned().fold(0.0, f32::max);
+  let amplitudes = rms
+   .into_iter()
+   .map(|v| if peak > 0.0 { v / peak } else { 0.0 })
+   .map(|v| {
+    if v < LIP_SYNC_NOISE_GATE {
+     0.0
+    } else {
+     (v * 100.0).round() / 100.0
+    }
+   })
+   .collect::<Vec<_>>();
+
+  let duration_in_ms = samples.len() as u64 * 1000 / (sample_rate as u64 * channels as u64).max(1);
+  let track = Self {
+   started_at: None,
+   audio_queue_id: None,
+   content: content.to_string(),
+   fps,
+   duration_in_ms,
+   amplitudes,
+  };
+
+  (SamplesBuffer::new(channels, sample_rate, samples), track)
+ }
+}
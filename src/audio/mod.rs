@@ -0,0 +1,265 @@
+mod cache;
+mod clips;
+mod lip_sync;
+mod queue;
+mod queues;
+
+pub use cache::{AudioCache, DEFAULT_AUDIO_CACHE_MAX_SIZE_IN_MB};
+pub use clips::{AudioClips, DEFAULT_AUDIO_CLIP_TTL_IN_SECS};
+pub use lip_sync::{LipSyncTrack, DEFAULT_LIP_SYNC_FPS};
+pub use queue::{
+ AudioCommand, AudioQueue, AudioQueueEntry, AudioQueueHook, AudioQueueHooks, AudioQueueReceiver, AudioQueueStatus, SharedAudioQueue,
+ MAX_AUDIO_VOLUME_IN_PERCENT,
+};
+pub use queues::AudioQueues;
+
+use crate::{Arc, Conf, Mutex};
+use anyhow::{Context, Result};
+use rodio::cpal::traits::HostTrait;
+use rodio::source::UniformSourceIterator;
+use rodio::{DeviceTrait, OutputStream, OutputStreamBuilder, Sink};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const DEFAULT_AUDIO_WAV_PATH: &str = "audio";
+const DEFAULT_AUDIO_WAV_FILE_MAX_SECS: u64 = 600;
+
+/// ヘッドレス出力時の音声の形式
+const HEADLESS_CHANNELS: u16 = 2;
+const HEADLESS_SAMPLE_RATE: u32 = 48000;
+/// ヘッドレス出力時に1度に処理する時間
+const HEADLESS_CHUNK_IN_MS: u64 = 10;
+
+pub struct AudioSink(pub Sink);
+pub type SharedAudioSink = Arc<Mutex<AudioSink>>;
+impl std::fmt::Debug for AudioSink {
+ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+  write!(f, "AudioSink")
+ }
+}
+
+/// 音声の出力先
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioBackend {
+ /// OS の既定の音声出力デバイス
+ Default,
+ /// 何も出力しない。再生は実時間どおりに進むため再生の完了待ちなどは通常と同じように動作します。
+ Null,
+ /// 再生した音声を max_secs 秒ごとに区切った WAV ファイルとして path のディレクトリーへ書き出す
+ WavFile { path: PathBuf, max_secs: u64 },
+}
+
+impl AudioBackend {
+ pub fn new(conf: &Conf) -> Self {
+  match conf.audio_backend.as_deref().map(|b| b.to_lowercase()).as_deref() {
+   None | Some("default") => Self::Default,
+   Some("null") | Some("none") => Self::Null,
+   Some("wav") | Some("wav-file") => Self::WavFile {
+    path: PathBuf::from(conf.audio_wav_path.as_deref().unwrap_or(DEFAULT_AUDIO_WAV_PATH)),
+    max_secs: conf.audio_wav_file_max_secs.unwrap_or(DEFAULT_AUDIO_WAV_FILE_MAX_SECS),
+   },
+   Some(backend) => {
+    log::warn!("audio_backend = {:?} は不明な値のため default として扱います。", backend);
+    Self::Default
+   },
+  }
+ }
+}
+
+/// 音声の再生に必要な出力ストリームなどを保持します。
+/// Sink は Shared 化できるが、 OutputStream は Shared 化できないため、再生を続ける間は呼び出し元で保持して下さい。
+pub struct AudioOutput {
+ pub audio_sink: SharedAudioSink,
+ _stream: Option<OutputStream>,
+}
+
+impl AudioOutput {
+ /// backend に応じて AudioSink を生成します。
+ /// Default で音声出力デバイスを開けなかった場合はサーバーやコンテナーでも動作を続けられるよう Null へ切り替えます。
+ pub fn open(backend: &AudioBackend) -> Result<Self> {
+  let (sink, stream) = match backend {
+   AudioBackend::Default => match rodio::OutputStreamBuilder::open_default_stream() {
+    Ok(stream) => {
+     log::info!("OS の既定の音声出力デバイスで再生します。");
+     (Sink::connect_new(stream.mixer()), Some(stream))
+    },
+    Err(e) => {
+     log::warn!(
+      "音声出力デバイスを開けなかったため audio_backend = \"null\" として動作します。音声は再生されません: {:?}",
+      e
+     );
+     (open_headless(None), None)
+    },
+   },
+   AudioBackend::Null => {
+    log::info!("audio_backend = \"null\" のため音声は再生されません。");
+    (open_headless(None), None)
+   },
+   AudioBackend::WavFile { path, max_secs } => {
+    log::info!(
+     "audio_backend = \"wav\" のため音声は {:?} へ WAV ファイルとして書き出されます。",
+     path
+    );
+    let writer = RollingWavWriter::new(path.clone(), *max_secs);
+    (open_headless(Some(writer)), None)
+   },
+  };
+
+  Ok(Self {
+   audio_sink: Arc::new(Mutex::new(AudioSink(sink))),
+   _stream: stream,
+  })
+ }
+}
+
+/// OS の音声出力デバイスの名前の一覧を返します。
+pub fn output_device_names() -> Result<Vec<String>> {
+ let host = rodio::cpal::default_host();
+ Ok(host.output_devices()?.filter_map(|d| d.name().ok()).collect())
+}
+
+/// OS の既定の音声出力デバイスの名前を返します。
+pub fn default_output_device_name() -> Option<String> {
+ rodio::cpal::default_host().default_output_device().and_then(|d| d.name().ok())
+}
+
+/// 名前で指定した音声出力デバイスの Sink を生成します。
+/// 名前が完全に一致するデバイスが無い場合は大文字と小文字を区別せずに名前の一部が一致するデバイスを使います。
+/// OutputStream は Send ではなく、破棄すると再生が止まるため、デバイスごとに専用のスレッドで保持し続けます。
+pub fn open_device_sink(name: &str) -> Result<Sink> {
+ let (sender, receiver) = std::sync::mpsc::channel();
+ let name = name.to_string();
+
+ std::thread::spawn(move || {
+  let stream = match open_device_stream(&name) {
+   Ok(stream) => stream,
+   Err(e) => {
+    let _ = sender.send(Err(e));
+    return;
+   },
+  };
+  if sender.send(Ok(Sink::connect_new(stream.mixer()))).is_err() {
+   return;
+  }
+  loop {
+   std::thread::park();
+  }
+ });
+
+ receiver.recv()?
+}
+
+/// 設定された name で実際に開かれる音声出力デバイスの名前を names から探します。
+/// 名前が完全に一致するものが無い場合は大文字と小文字を区別せずに名前の一部が一致するものを返します。
+pub fn resolve_device_name<'a>(names: &'a [String], name: &str) -> Option<&'a String> {
+ let lowercase_name = name.to_lowercase();
+ names
+  .iter()
+  .find(|n| *n == name)
+  .or_else(|| names.iter().find(|n| n.to_lowercase().contains(&lowercase_name)))
+}
+
+fn open_device_stream(name: &str) -> Result<OutputStream> {
+ let host = rodio::cpal::default_host();
+ let (names, devices): (Vec<_>, Vec<_>) = host.output_devices()?.filter_map(|d| Some((d.name().ok()?, d))).unzip();
+ let device = resolve_device_name(&names, name)
+  .and_then(|resolved| names.iter().position(|n| n == resolved))
+  .map(|index| devices[index].clone())
+  .with_context(|| format!("音声出力デバイス {:?} が見つかりませんでした。", name))?;
+ log::debug!("音声出力デバイス {:?} を開きます: {:?}", name, device.name());
+ Ok(OutputStreamBuilder::from_device(device)?.open_stream_or_fallback()?)
+}
+
+/// 音声出力デバイスの代わりに実時間で Sink から音声を取り出すスレッドを動かします。
+fn open_headless(mut writer: Option<RollingWavWriter>) -> Sink {
+ let (sink, queue) = Sink::new();
+
+ std::thread::spawn(move || {
+  let mut source = UniformSourceIterator::new(queue, HEADLESS_CHANNELS, HEADLESS_SAMPLE_RATE);
+  let chunk_len = (HEADLESS_SAMPLE_RATE as u64 * HEADLESS_CHANNELS as u64 * HEADLESS_CHUNK_IN_MS / 1000) as usize;
+  let started = Instant::now();
+  let mut chunks = 0;
+
+  loop {
+   let samples = source.by_ref().take(chunk_len).collect::<Vec<_>>();
+   if samples.is_empty() {
+    break;
+   }
+
+   // 何も再生していない間の無音は書き出さない
+   if let Some(writer) = writer.as_mut() {
+    if samples.iter().any(|s| *s != 0.0) {
+     if let Err(e) = writer.write(&samples) {
+      log::error!("WAV ファイルへの書き出しに失敗しました: {:?}", e);
+     }
+    }
+   }
+
+   // 実時間にあわせて待機
+   chunks += 1;
+   let next = started + Duration::from_millis(chunks * HEADLESS_CHUNK_IN_MS);
+   if let Some(wait) = next.checked_duration_since(Instant::now()) {
+    std::thread::sleep(wait);
+   }
+  }
+ });
+
+ sink
+}
+
+/// 一定の長さごとにファイルを切り替えながら WAV ファイルを書き出します。
+struct RollingWavWriter {
+ path: PathBuf,
+ max_samples: u64,
+ writer: Option<hound::WavWriter<BufWriter<File>>>,
+ written_samples: u64,
+}
+
+impl RollingWavWriter {
+ fn new(path: PathBuf, max_secs: u64) -> Self {
+  Self {
+   path,
+   max_samples: max_secs.max(1) * HEADLESS_SAMPLE_RATE as u64 * HEADLESS_CHANNELS as u64,
+   writer: None,
+   written_samples: 0,
+  }
+ }
+
+ fn write(&mut self, samples: &[f32]) -> Result<()> {
+  if self.writer.is_none() || self.written_samples >= self.max_samples {
+   self.rotate()?;
+  }
+
+  let writer = self.writer.as_mut().unwrap();
+  for sample in samples {
+   writer.write_sample(*sample)?;
+  }
+  // 強制終了されても書き出し済みの部分は再生できるようヘッダーを更新
+  writer.flush()?;
+  self.written_samples += samples.len() as u64;
+  Ok(())
+ }
+
+ fn rotate(&mut self) -> Result<()> {
+  if let Some(writer) = self.writer.take() {
+   writer.finalize()?;
+  }
+
+  std::fs::create_dir_all(&self.path)?;
+  let path = self
+   .path
+   .join(format!("vac-audio-{}.wav", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+  log::info!("音声を {:?} へ書き出します。", path);
+  let spec = hound::WavSpec {
+   channels: HEADLESS_CHANNELS,
+   sample_rate: HEADLESS_SAMPLE_RATE,
+   bits_per_sample: 32,
+   sample_format: hound::SampleFormat::Float,
+  };
+  self.writer = Some(hound::WavWriter::create(path, spec)?);
+  self.written_samples = 0;
+  Ok(())
+ }
+}
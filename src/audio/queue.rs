@@ -0,0 +1,349 @@
+use super::SharedAudioSink;
+use crate::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{watch, Notify, OwnedMutexGuard};
+use tokio::time::Duration;
+
+/// 再生の終了を確認する間隔
+const PLAYBACK_POLLING_INTERVAL_IN_MS: u64 = 20;
+/// 音量の上限 [%]
+pub const MAX_AUDIO_VOLUME_IN_PERCENT: f32 = 200.0;
+
+/// 再生キューの ID は音声出力デバイスごとの再生キューを通して一意
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+pub type SharedAudioQueue = Arc<AudioQueue>;
+pub type AudioQueueReceiver = watch::Receiver<AudioQueueStatus>;
+/// 音声の再生の開始や終了の時にその音声の情報を与えて呼ばれる処理
+pub type AudioQueueHook = Box<dyn FnOnce(AudioQueueEntry) + Send>;
+
+/// 再生キューへ音声と一緒に追加する処理群。再生されずに飛ばされたり破棄された音声ではどちらも呼ばれません。
+#[derive(Default)]
+pub struct AudioQueueHooks {
+ /// 音声の再生を開始した時
+ pub on_start: Option<AudioQueueHook>,
+ /// 音声の再生が終わった時。再生中に飛ばされたり止められた場合も呼ばれます。
+ pub on_end: Option<AudioQueueHook>,
+}
+
+/// 再生待ちまたは再生中の音声の情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioQueueEntry {
+ /// 再生キューの中で音声を指定するための ID
+ pub id: u64,
+ /// 音声を追加した Processor の ID
+ pub processor: String,
+ /// 再生する音声出力デバイスの名前。既定の出力先の場合は None
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub device: Option<String>,
+ /// 音声の元になった ChannelDatum の ID
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub source_id: Option<u64>,
+ /// 読み上げる内容
+ pub content: String,
+ /// 音声の長さ。分からない場合は None
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub duration_in_ms: Option<u64>,
+ pub queued_at: DateTime<Utc>,
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub started_at: Option<DateTime<Utc>>,
+}
+
+/// 再生キューの状態。 REST の /audio/queue や WebSocket で配信されます。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioQueueStatus {
+ /// 再生中の音声。音声出力デバイスごとに1つずつ再生されます。
+ pub playing: Vec<AudioQueueEntry>,
+ /// 再生待ちの音声
+ pub queued: Vec<AudioQueueEntry>,
+ /// 音量 [%]
+ pub volume: f32,
+}
+
+impl Default for AudioQueueStatus {
+ fn default() -> Self {
+  Self {
+   playing: vec![],
+   queued: vec![],
+   volume: 100.0,
+  }
+ }
+}
+
+/// 再生キューの操作。 Command の /skip などと REST の /audio 、 WebSocket の audio で共通です。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum AudioCommand {
+ /// id を省略すると再生中の音声を、指定すると再生待ちの音声を飛ばします。
+ Skip {
+  #[serde(default)]
+  id: Option<u64>,
+ },
+ /// 再生中の音声を止め、再生待ちの音声も全て破棄します。
+ Stop,
+ /// 再生待ちの音声を全て破棄します。再生中の音声はそのまま再生されます。
+ ClearAudio,
+ /// 音量 [%] を設定します。
+ Volume { volume: f32 },
+ /// 何もせずに状態だけを取得します。
+ Status,
+}
+
+struct AudioQueueItem {
+ entry: AudioQueueEntry,
+ source: Box<dyn Source + Send>,
+ hooks: AudioQueueHooks,
+}
+
+#[derive(Default)]
+struct AudioQueueInner {
+ playing: Option<AudioQueueEntry>,
+ queued: VecDeque<AudioQueueItem>,
+}
+
+/// 1つの音声出力デバイスへ音声合成系の Processor 群が共有する再生キューです。
+/// 音声は1つずつ audio_sink へ送出されるため、再生中や再生待ちの音声を個別に飛ばしたり破棄できます。
+pub struct AudioQueue {
+ audio_sink: SharedAudioSink,
+ device: Option<String>,
+ inner: Mutex<AudioQueueInner>,
+ notify: Notify,
+ producer: Arc<Mutex<()>>,
+ status: watch::Sender<AudioQueueStatus>,
+}
+
+impl std::fmt::Debug for AudioQueue {
+ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+  write!(f, "AudioQueue")
+ }
+}
+
+impl AudioQueue {
+ /// 再生キューを生成し、再生を進めるタスクを開始します。
+ pub fn spawn(audio_sink: SharedAudioSink, device: Option<String>) -> SharedAudioQueue {
+  let (status, _) = watch::channel(AudioQueueStatus::default());
+  let audio_queue = Arc::new(Self {
+   audio_sink,
+   device,
+   inner: Mutex::new(AudioQueueInner::default()),
+   notify: Notify::new(),
+   producer: Arc::new(Mutex::new(())),
+   status,
+  });
+
+  let q = audio_queue.clone();
+  tokio::spawn(async move { q.run().await });
+
+  audio_queue
+ }
+
+ /// 1つの発話を複数の音声に分けて追加する場合に、他の発話の音声が間に割り込まないよう追加し終えるまで保持します。
+ pub async fn lock_producer(&self) -> OwnedMutexGuard<()> {
+  self.producer.clone().lock_owned().await
+ }
+
+ /// 音声を再生キューの末尾へ追加し、再生キューの中での ID を返します。
+ pub async fn append<S>(&self, source: S, processor: &str, source_id: Option<u64>, content: &str) -> u64
+ where
+  S: Source + Send + 'static,
+ {
+  self
+   .append_with_hooks(source, processor, source_id, content, AudioQueueHooks::default())
+   .await
+ }
+
+ /// append に加えて、音声の再生の開始と終了の時に hooks を呼びます。
+ pub async fn append_with_hooks<S>(&self, source: S, processor: &str, source_id: Option<u64>, content: &str, hooks: AudioQueueHooks) -> u64
+ where
+  S: Source + Send + 'static,
+ {
+  let id = {
+   let mut inner = self.inner.lock().await;
+   let id = LAST_ID.fetch_add(1, Ordering::SeqCst) + 1;
+   let entry = AudioQueueEntry {
+    id,
+    processor: processor.to_string(),
+    device: self.device.clone(),
+    source_id,
+    content: content.to_string(),
+    duration_in_ms: source.total_duration().map(|d| d.as_millis() as u64),
+    queued_at: Utc::now(),
+    started_at: None,
+   };
+   log::trace!("再生キューに音声を追加します: {:?}", entry);
+   inner.queued.push_back(AudioQueueItem {
+    entry,
+    source: Box::new(source),
+    hooks,
+   });
+   id
+  };
+  self.notify.notify_one();
+  self.publish().await;
+  id
+ }
+
+ /// id が None なら再生中の音声を、 Some なら再生待ちの音声を飛ばします。該当する音声が無かった場合は false を返します。
+ pub async fn skip(&self, id: Option<u64>) -> bool {
+  let skipped = {
+   let mut inner = self.inner.lock().await;
+   match id {
+    // 再生中ではない音声は再生待ちから取り除く
+    Some(id) if inner.playing.as_ref().map(|e| e.id) != Some(id) => {
+     let len = inner.queued.len();
+     inner.queued.retain(|item| item.entry.id != id);
+     inner.queued.len() != len
+    },
+    _ if inner.playing.is_some() => {
+     self.audio_sink.lock().await.0.skip_one();
+     true
+    },
+    _ => false,
+   }
+  };
+  if skipped {
+   self.publish().await;
+  }
+  skipped
+ }
+
+ /// 再生待ちの音声を全て破棄し、破棄した数を返します。
+ pub async fn clear(&self) -> usize {
+  let cleared = {
+   let mut inner = self.inner.lock().await;
+   let len = inner.queued.len();
+   inner.queued.clear();
+   len
+  };
+  self.publish().await;
+  cleared
+ }
+
+ /// 再生待ちの音声を全て破棄して再生中の音声も止め、止めた音声の数を返します。
+ pub async fn stop(&self) -> usize {
+  let mut stopped = self.clear().await;
+  if self.skip(None).await {
+   stopped += 1;
+  }
+  stopped
+ }
+
+ /// 音量 [%] を設定し、上限などで補正した後の音量を返します。
+ pub async fn set_volume(&self, volume: f32) -> f32 {
+  let volume = match volume.is_finite() {
+   true => volume.clamp(0.0, MAX_AUDIO_VOLUME_IN_PERCENT),
+   false => 100.0,
+  };
+  self.audio_sink.lock().await.0.set_volume(volume / 100.0);
+  self.publish().await;
+  volume
+ }
+
+ pub async fn volume(&self) -> f32 {
+  self.audio_sink.lock().await.0.volume() * 100.0
+ }
+
+ pub async fn status(&self) -> AudioQueueStatus {
+  let volume = self.volume().await;
+  let inner = self.inner.lock().await;
+  AudioQueueStatus {
+   playing: inner.playing.iter().cloned().collect(),
+   queued: inner.queued.iter().map(|item| item.entry.clone()).collect(),
+   volume,
+  }
+ }
+
+ /// 再生キューの状態の変化を購読します。
+ pub fn subscribe(&self) -> AudioQueueReceiver {
+  self.status.subscribe()
+ }
+
+ async fn publish(&self) {
+  let status = self.status().await;
+  self.status.send_replace(status);
+ }
+
+ /// 再生待ちの音声を1つずつ audio_sink へ送出し、再生が終わるまで待機することを繰り返します。
+ async fn run(&self) {
+  loop {
+   // 再生中にしてから audio_sink へ送出するまでの間に skip されて空の audio_sink を飛ばしてしまわないよう、 inner を保持したまま送出
+   let item = {
+    let mut inner = self.inner.lock().await;
+    match inner.queued.pop_front() {
+     Some(AudioQueueItem { mut entry, source, hooks }) => {
+      entry.started_at = Some(Utc::now());
+      log::trace!("再生キューの音声を再生します: {:?}", entry);
+      inner.playing = Some(entry.clone());
+      self.audio_sink.lock().await.0.append(source);
+      Some((entry, hooks))
+     },
+     None => {
+      inner.playing = None;
+      None
+     },
+    }
+   };
+
+   let (entry, hooks) = match item {
+    Some(item) => item,
+    None => {
+     self.publish().await;
+     self.notify.notified().await;
+     continue;
+    },
+   };
+
+   if let Some(on_start) = hooks.on_start {
+    on_start(entry.clone());
+   }
+   self.publish().await;
+
+   while !self.audio_sink.lock().await.0.empty() {
+    tokio::time::sleep(Duration::from_millis(PLAYBACK_POLLING_INTERVAL_IN_MS)).await;
+   }
+
+   log::trace!("再生キューの音声の再生が終わりました: {:?}", entry.id);
+   // 次の音声を取り出すまでの間に skip されても再生中の音声が無いと分かるように
+   self.inner.lock().await.playing = None;
+   if let Some(on_end) = hooks.on_end {
+    on_end(entry);
+   }
+  }
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::{AudioBackend, AudioOutput};
+
+ #[tokio::test]
+ async fn skip_right_after_append_does_not_play_the_audio() {
+  let audio_output = AudioOutput::open(&AudioBackend::Null).unwrap();
+  let audio_queue = AudioQueue::spawn(audio_output.audio_sink.clone(), None);
+
+  let (ended, on_end) = tokio::sync::oneshot::channel();
+  let hooks = AudioQueueHooks {
+   on_start: None,
+   on_end: Some(Box::new(move |_| {
+    let _ = ended.send(Utc::now());
+   })),
+  };
+  let source = rodio::source::Zero::new(1, 48000).take_duration(Duration::from_secs(5));
+  let started = Utc::now();
+  audio_queue.append_with_hooks(source, "test", None, "skipped", hooks).await;
+
+  // 再生中になった直後に飛ばす
+  while !audio_queue.skip(None).await {
+   tokio::task::yield_now().await;
+  }
+
+  let ended = tokio::time::timeout(Duration::from_secs(2), on_end).await.unwrap().unwrap();
+  assert!(ended - started < chrono::Duration::seconds(2));
+  assert!(audio_output.audio_sink.lock().await.0.empty());
+ }
+}
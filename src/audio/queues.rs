@@ -0,0 +1,171 @@
+use super::{open_device_sink, AudioCommand, AudioQueue, AudioQueueStatus, AudioSink, SharedAudioQueue};
+use crate::{Arc, Mutex};
+use std::collections::BTreeMap;
+use tokio::sync::watch;
+
+/// 既定の出力先と音声出力デバイスごとの再生キュー群です。
+/// 音声出力デバイスの再生キューは最初に使われる時に生成され、同じデバイスを使う Processor 群で共有されます。
+#[derive(Debug, Clone)]
+pub struct AudioQueues {
+ default: SharedAudioQueue,
+ devices: Arc<Mutex<BTreeMap<String, SharedAudioQueue>>>,
+ status: Arc<watch::Sender<AudioQueueStatus>>,
+}
+
+impl AudioQueues {
+ pub fn new(default: SharedAudioQueue) -> Self {
+  let (status, _) = watch::channel(AudioQueueStatus::default());
+  let audio_queues = Self {
+   default: default.clone(),
+   devices: Arc::new(Mutex::new(BTreeMap::new())),
+   status: Arc::new(status),
+  };
+  audio_queues.relay(&default);
+  audio_queues
+ }
+
+ /// device が None なら既定の出力先の、 Some ならその音声出力デバイスの再生キューを返します。
+ /// 音声出力デバイスを開けなかった場合は既定の出力先の再生キューを返します。
+ pub async fn get(&self, device: Option<&str>) -> SharedAudioQueue {
+  let device = match device {
+   Some(device) => device,
+   None => return self.default.clone(),
+  };
+
+  let mut devices = self.devices.lock().await;
+  if let Some(audio_queue) = devices.get(device) {
+   return audio_queue.clone();
+  }
+
+  let name = device.to_string();
+  let sink = match tokio::task::spawn_blocking(move || open_device_sink(&name)).await {
+   Ok(Ok(sink)) => sink,
+   Ok(Err(e)) => {
+    log::error!(
+     "音声出力デバイス {:?} を開けなかったため既定の出力先で再生します。デバイスの名前は /status で確認できます: {:?}",
+     device,
+     e
+    );
+    return self.default.clone();
+   },
+   Err(e) => {
+    log::error!("音声出力デバイス {:?} を開く処理が異常終了しました: {:?}", device, e);
+    return self.default.clone();
+   },
+  };
+  log::info!("音声出力デバイス {:?} の再生キューを生成しました。", device);
+
+  let audio_queue = AudioQueue::spawn(Arc::new(Mutex::new(AudioSink(sink))), Some(device.to_string()));
+  audio_queue.set_volume(self.default.volume().await).await;
+  devices.insert(device.to_string(), audio_queue.clone());
+  self.relay(&audio_queue);
+  audio_queue
+ }
+
+ /// 既定の出力先と生成済みの全ての音声出力デバイスの再生キュー
+ pub async fn all(&self) -> Vec<SharedAudioQueue> {
+  let devices = self.devices.lock().await;
+  std::iter::once(self.default.clone()).chain(devices.values().cloned()).collect()
+ }
+
+ /// 生成済みの音声出力デバイスの再生キューのデバイス名
+ pub async fn device_names(&self) -> Vec<String> {
+  self.devices.lock().await.keys().cloned().collect()
+ }
+
+ /// id が None なら全ての再生中の音声を、 Some ならその ID の音声を飛ばします。該当する音声が無かった場合は false を返します。
+ pub async fn skip(&self, id: Option<u64>) -> bool {
+  let mut skipped = false;
+  for audio_queue in self.all().await {
+   skipped |= audio_queue.skip(id).await;
+  }
+  skipped
+ }
+
+ /// 全ての再生キューの再生待ちの音声を破棄し、破棄した数を返します。
+ pub async fn clear(&self) -> usize {
+  let mut cleared = 0;
+  for audio_queue in self.all().await {
+   cleared += audio_queue.clear().await;
+  }
+  cleared
+ }
+
+ /// 全ての再生キューの再生を止めて再生待ちの音声も破棄し、止めた音声の数を返します。
+ pub async fn stop(&self) -> usize {
+  let mut stopped = 0;
+  for audio_queue in self.all().await {
+   stopped += audio_queue.stop().await;
+  }
+  stopped
+ }
+
+ /// 全ての再生キューの音量 [%] を設定し、補正した後の音量を返します。
+ pub async fn set_volume(&self, volume: f32) -> f32 {
+  let mut result = volume;
+  for audio_queue in self.all().await {
+   result = audio_queue.set_volume(volume).await;
+  }
+  result
+ }
+
+ /// 既定の出力先の音量 [%]
+ pub async fn volume(&self) -> f32 {
+  self.default.volume().await
+ }
+
+ /// 全ての再生キューをまとめた状態
+ pub async fn status(&self) -> AudioQueueStatus {
+  let mut status = AudioQueueStatus {
+   volume: self.volume().await,
+   ..Default::default()
+  };
+  for audio_queue in self.all().await {
+   let s = audio_queue.status().await;
+   status.playing.extend(s.playing);
+   status.queued.extend(s.queued);
+  }
+  status.queued.sort_by_key(|e| e.id);
+  status
+ }
+
+ /// 全ての再生キューをまとめた状態の変化を購読します。
+ pub fn subscribe(&self) -> watch::Receiver<AudioQueueStatus> {
+  self.status.subscribe()
+ }
+
+ /// AudioCommand を実行し、実行後の状態を返します。
+ pub async fn execute(&self, command: &AudioCommand) -> AudioQueueStatus {
+  log::debug!("再生キューの操作を実行します: {:?}", command);
+  match command {
+   AudioCommand::Skip { id } => {
+    if !self.skip(*id).await {
+     log::debug!("飛ばす音声が見つかりませんでした: id = {:?}", id);
+    }
+   },
+   AudioCommand::Stop => {
+    self.stop().await;
+   },
+   AudioCommand::ClearAudio => {
+    self.clear().await;
+   },
+   AudioCommand::Volume { volume } => {
+    self.set_volume(*volume).await;
+   },
+   AudioCommand::Status => (),
+  }
+  self.status().await
+ }
+
+ /// 個々の再生キューの状態が変わる度にまとめた状態を配信
+ fn relay(&self, audio_queue: &SharedAudioQueue) {
+  let mut receiver = audio_queue.subscribe();
+  let audio_queues = self.clone();
+  tokio::spawn(async move {
+   while receiver.changed().await.is_ok() {
+    let status = audio_queues.status().await;
+    audio_queues.status.send_replace(status);
+   }
+  });
+ }
+}
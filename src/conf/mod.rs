@@ -1,4 +1,5 @@
 mod processor_conf;
+mod processor_graph;
 
 pub use anyhow::{bail, Result};
 pub use processor_conf::*;
@@ -11,6 +12,7 @@ use std::path::PathBuf;
 pub type SharedConf = Arc<RwLock<Conf>>;
 
 pub const DEFAULT_WEB_UI_ADDRESS: &str = "127.0.0.1:57000";
+pub const DEFAULT_SYSTEM_CHANNEL: &str = "vac-system";
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
@@ -30,6 +32,28 @@ pub struct Twitch {
  pub channel_to: String,
 }
 
+/// チャンネルごとに channel_data へ保持する ChannelDatum の規則です。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ChannelRetentionConf {
+ pub channel: String,
+ /// このチャンネルで保持する件数の上限
+ pub capacity: Option<usize>,
+ /// この秒数を過ぎた ChannelDatum を削除 (state_data_ttl_in_secs より優先)
+ pub ttl_in_secs: Option<u64>,
+ /// 最新のこの件数の確定済みの ChannelDatum は ttl や state_data_capacity では削除しない
+ pub keep_latest_final: Option<usize>,
+ /// true の場合は ttl や state_data_capacity では削除しない
+ pub pinned: Option<bool>,
+}
+
+/// 名前を付けた音声の出力先です。 Processor の audio_sink から名前で指定できます。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct AudioSinkConf {
+ pub name: String,
+ /// 音声出力デバイスの名前
+ pub device: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Conf {
  pub workers: Option<usize>,
@@ -43,6 +67,30 @@ pub struct Conf {
  pub state_data_path: Option<PathBuf>,
  pub state_data_capacity: Option<usize>,
  pub state_data_pretty: Option<bool>,
+ pub state_data_format: Option<String>,
+ pub state_data_journal_compaction_threshold: Option<usize>,
+ pub state_data_ttl_in_secs: Option<u64>,
+ #[serde(default)]
+ pub state_data_pinned_channels: Vec<String>,
+ #[serde(default)]
+ pub state_data_channels: Vec<ChannelRetentionConf>,
+
+ pub processor_hop_limit: Option<usize>,
+
+ pub channel_event_bus_capacity: Option<usize>,
+
+ pub conf_hot_reload: Option<bool>,
+ pub conf_hot_reload_interval_in_ms: Option<u64>,
+ pub system_channel: Option<String>,
+
+ pub audio_backend: Option<String>,
+ pub audio_wav_path: Option<String>,
+ pub audio_wav_file_max_secs: Option<u64>,
+ #[serde(default)]
+ pub audio_sinks: Vec<AudioSinkConf>,
+ pub audio_clip_ttl_in_secs: Option<u64>,
+ pub audio_cache_path: Option<String>,
+ pub audio_cache_max_size_in_mb: Option<u64>,
 
  pub twitch: Option<Twitch>,
 
@@ -53,18 +101,23 @@ pub struct Conf {
 
  #[serde(default)]
  pub processors: Vec<ProcessorConf>,
+
+ /// 読み込み元の設定ファイルのパス。 /reload などの再読み込みに使用します。
+ #[serde(skip)]
+ pub conf_path: Option<PathBuf>,
 }
 
 impl Conf {
  fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
   let path = path.into();
   let conf = std::fs::read_to_string(&path)?;
-  let conf = toml::from_str(&conf)?;
+  let mut conf: Self = toml::from_str(&conf)?;
+  conf.conf_path = Some(path);
   Ok(conf)
  }
 
  pub fn new(args: &Args) -> Result<Self> {
-  let conf = Self::load(&args.conf)?;
+  let mut conf = Self::load(&args.conf)?;
   // ログレベルの設定(argsで--debugが指定されていない場合)
   if let Some(log_filter) = conf.log_level.as_ref() {
    let v = match log_filter.to_lowercase().as_str() {
@@ -93,9 +146,46 @@ impl Conf {
    }
   }
 
+  conf.validate_processors()?;
+
+  Ok(conf)
+ }
+
+ /// 実行中に設定ファイルを再度読み込みます。ログレベルは再設定しません。
+ pub fn reload<P: Into<PathBuf>>(path: P) -> Result<Self> {
+  let mut conf = Self::load(path)?;
+  conf.validate_processors()?;
+  Ok(conf)
+ }
+
+ fn validate_processors(&mut self) -> Result<()> {
+  // id が設定されていない processors には来歴やログで見分けるための id を "{feature}:{channel_from}>{channel_to}" で補完
+  // 定義の追加や削除で他の processors の id が変わらないよう定義順は使わず、同じ内容の id が既にある場合だけ "#2" などを付ける
+  let mut ids = self
+   .processors
+   .iter()
+   .filter_map(|p| p.id.clone())
+   .collect::<std::collections::HashSet<_>>();
+  for processor in self.processors.iter_mut().filter(|p| p.id.is_none()) {
+   let base = format!(
+    "{}:{}>{}",
+    processor.feature.as_deref().unwrap_or_default(),
+    processor.channel_from.as_deref().unwrap_or_default(),
+    processor.channel_to.as_deref().unwrap_or_default()
+   );
+   let mut id = base.clone();
+   let mut count = 1;
+   while ids.contains(&id) {
+    count += 1;
+    id = format!("{}#{}", base, count);
+   }
+   ids.insert(id.clone());
+   processor.id = Some(id);
+  }
+
   // processors に同一の id が指定されていないかチェック
   let mut already_used_ids = std::collections::HashSet::new();
-  for processor in conf.processors.iter() {
+  for processor in self.processors.iter() {
    if let Some(pid) = processor.id.as_ref() {
     if !already_used_ids.insert(pid.clone()) {
      log::error!(
@@ -107,7 +197,29 @@ impl Conf {
    }
   }
 
-  Ok(conf)
+  // audio_sink で名前を指定された出力先を audio_device へ解決
+  for processor in self.processors.iter_mut() {
+   if let Some(name) = processor.audio_sink.as_ref() {
+    match self.audio_sinks.iter().find(|s| &s.name == name) {
+     Some(audio_sink) => processor.audio_device = Some(audio_sink.device.clone()),
+     None => log::warn!(
+      "プロセッサー {:?} の audio_sink = {:?} は audio_sinks に定義されていないため audio_device の設定で再生します。",
+      processor.get_id(),
+      name
+     ),
+    }
+   }
+  }
+
+  // processors の channel_from/channel_to の組み合わせによるループを静的にチェック
+  for cycle in self.find_processor_cycles() {
+   log::warn!(
+    "processors の設定にループになり得る経路があります。実行時には processor_hop_limit と来歴による検出でループした入力は破棄されますが、意図した設定か確認して下さい。🙏 {}",
+    cycle
+   );
+  }
+
+  Ok(())
  }
 
  pub fn execute_run_with(&self) -> Result<()> {
@@ -223,6 +335,13 @@ impl Conf {
   self.workers.unwrap_or_else(|| num_cpus::get())
  }
 
+ pub fn get_system_channel(&self) -> &str {
+  match self.system_channel.as_ref() {
+   Some(c) => c,
+   None => DEFAULT_SYSTEM_CHANNEL,
+  }
+ }
+
  pub fn get_web_ui_address(&self) -> &str {
   match self.web_ui_address.as_ref() {
    Some(a) => a,
@@ -1,15 +1,16 @@
 use crate::{utility::bool_true, Arc, RwLock};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 pub type SharedProcessorConf = Arc<RwLock<ProcessorConf>>;
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct ContentWithChannel {
  pub channel: String,
  pub content: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct CommandSet {
  pub name: String,
  #[serde(default)]
@@ -21,7 +22,7 @@ pub struct CommandSet {
 }
 
 /// ファインチューニング関連の設定オプションです。
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum OpenAiChatFinetuning {
  /// Detail で path だけ設定した場合と同じ扱いになります。
@@ -65,7 +66,7 @@ impl OpenAiChatFinetuning {
  }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct ProcessorConf {
  // Common
  /// プロセッサーの定義ごとに個別に名付けを行えます。
@@ -147,10 +148,38 @@ pub struct ProcessorConf {
  pub translate_to: Option<String>,
  pub process_incomplete_input: Option<bool>,
 
- // CoeiroInk
+ // 音声合成する Processor 群 (CoeiroInk, VOICEVOX, HttpTts, OsTTS, Bouyomichan)
+ /// 設定すると合成した音声を {T} で日時を挿入したこのパスへ保存します。
+ pub audio_file_store_path: Option<String>,
+ /// 設定するとこの正規表現で入力を区切って順に音声合成します。
+ pub split_regex_pattern: Option<String>,
+ /// false にすると Conf の audio_cache_path の音声のキャッシュを使いません。(デフォルト: true)
+ pub audio_cache: Option<bool>,
+ /// split_regex_pattern で分割した文を前の文の再生中に先行して音声合成する数。 1 にすると 1 文ずつ順番に音声合成します。(デフォルト: 2)
+ pub synthesis_concurrency: Option<usize>,
+ /// 読み上げる内容から [style:angry] などの読み上げのタグを除去した字幕用の内容を送出するチャンネル
+ pub subtitle_channel: Option<String>,
+ /// 音声出力デバイスの名前。未設定の場合は既定の出力先で再生します。
+ pub audio_device: Option<String>,
+ /// Conf の audio_sinks で名付けた出力先の名前。設定すると audio_device はその出力先の device で上書きされます。
+ pub audio_sink: Option<String>,
+ /// false にすると VAC を動かしている PC では再生しません。 channel_to と組み合わせてブラウザーで再生する場合に使います。(デフォルト: true)
+ pub audio_play_locally: Option<bool>,
+ /// 設定すると音声の再生を開始した時にアバターの口パク用の音量の推移を JSON でこのチャンネルへ送出します。
+ pub lip_sync_channel: Option<String>,
+ /// 口パク用の音量の推移の 1 秒あたりのフレーム数 (デフォルト: 30)
+ pub lip_sync_fps: Option<u32>,
+ /// 設定すると音声の再生の開始と終了の時に speech_start / speech_end フラグ付きで読み上げた内容をこのチャンネルへ送出します。
+ pub speech_event_channel: Option<String>,
+
+ // CoeiroInk, VOICEVOX (api_url, style_id と *_scale などの合成のパラメーター)
  pub api_url: Option<String>,
  pub speaker_uuid: Option<String>,
  pub style_id: Option<i64>,
+ /// CoeiroInk の話者の名前。設定すると起動時と CoeiroInk の再起動時に speaker_uuid を API で解決します。
+ pub speaker_name: Option<String>,
+ /// CoeiroInk のスタイルの名前。設定すると起動時と CoeiroInk の再起動時に style_id を API で解決します。
+ pub style_name: Option<String>,
  pub speed_scale: Option<f64>,
  pub volume_scale: Option<f64>,
  pub pitch_scale: Option<f64>,
@@ -158,13 +187,28 @@ pub struct ProcessorConf {
  pub pre_phoneme_length: Option<f64>,
  pub post_phoneme_length: Option<f64>,
  pub output_sampling_rate: Option<u32>,
- pub audio_file_store_path: Option<String>,
- pub split_regex_pattern: Option<String>,
  pub processing_algorithm: Option<String>,
 
+ // http-tts ({text} などの {名前} は値で置換されます)
+ /// HTTP のメソッド (デフォルト: POST)
+ pub http_method: Option<String>,
+ /// 追加する HTTP ヘッダー
+ #[serde(default)]
+ pub http_headers: BTreeMap<String, String>,
+ /// URL に追加するクエリー
+ #[serde(default)]
+ pub http_query: BTreeMap<String, String>,
+ /// JSON の本文
+ pub http_body: Option<String>,
+ /// 応答が JSON の場合に base64 の音声データの位置を表す JSON Pointer (例: "/audio")。未設定の場合は応答そのものを音声データとして扱います。
+ pub http_response_audio_json_pointer: Option<String>,
+
  // BouyomiChan
+ /// 以前の RemoteTalk.exe のパス。現在は使われません。
  pub remote_talk_path: Option<String>,
+ /// 棒読みちゃんの IP アドレスまたはホスト名 (TCP) 、または http:// で始まる URL (HTTP) (デフォルト: 127.0.0.1)
  pub address: Option<String>,
+ /// 棒読みちゃんのポート (デフォルト: TCP は 50001 、 HTTP は 50080)
  pub port: Option<u16>,
  pub voice: Option<i16>,
  pub speed: Option<i16>,
@@ -183,4 +227,16 @@ impl ProcessorConf {
  pub fn as_shared(&self) -> SharedProcessorConf {
   Arc::new(RwLock::new(self.clone()))
  }
+
+ /// 来歴やログで Processor を見分けるための ID を返します。
+ /// id が設定されていない場合は Conf の読み込み時に "{feature}:{channel_from}>{channel_to}" (例: "gas-translation:user>user-en") が補完されます。
+ /// 同じ内容の id が既にある場合は "gas-translation:user>user-en#2" のように番号が付きます。
+ pub fn get_id(&self) -> String {
+  self.id.clone().or_else(|| self.feature.clone()).unwrap_or_default()
+ }
+
+ /// group に指定されたグループ名が含まれるか
+ pub fn is_in_group(&self, group: &str) -> bool {
+  self.group.iter().any(|g| g == group)
+ }
 }
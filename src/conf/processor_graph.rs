@@ -0,0 +1,81 @@
+use super::Conf;
+use crate::{Command, Modify, Processor};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// channel_from -> channel_to の有向辺。 via は辺を作る Processor の ID です。
+#[derive(Debug, Clone)]
+struct Edge {
+ to: String,
+ via: String,
+}
+
+impl Conf {
+ /// processors の channel_from/channel_to (と command の set の送出先) から作られるチャンネルの有向グラフを静的に調べ、
+ /// ループになり得る経路を "user -[gas-translation:user>user-en]-> user-en -[gas-translation:user-en>user]-> user" のような表記で返します。
+ /// 実際にループするかは各 Processor の条件次第のため、ここでは検出のみを行います。
+ pub fn find_processor_cycles(&self) -> Vec<String> {
+  let mut graph = BTreeMap::<String, Vec<Edge>>::new();
+
+  for pc in self.processors.iter() {
+   let (channel_from, feature) = match (&pc.channel_from, &pc.feature) {
+    (Some(channel_from), Some(feature)) => (channel_from, feature.to_lowercase()),
+    _ => continue,
+   };
+   let via = pc.get_id();
+
+   let mut channels_to = pc.channel_to.iter().cloned().collect::<BTreeSet<_>>();
+   if feature == Command::FEATURE {
+    channels_to.extend(pc.set.iter().flat_map(|s| s.channel_contents.iter().map(|cc| cc.channel.clone())));
+   }
+
+   for channel_to in channels_to {
+    // modify は channel_from と channel_to が同一の場合は新たな入力を発生させず内容を書き換えるだけなのでループにならない
+    if feature == Modify::FEATURE && &channel_to == channel_from {
+     continue;
+    }
+    graph.entry(channel_from.clone()).or_default().push(Edge {
+     to: channel_to,
+     via: via.clone(),
+    });
+   }
+  }
+
+  let mut cycles = vec![];
+  let mut visited = BTreeSet::new();
+  let channels = graph.keys().cloned().collect::<Vec<_>>();
+  for channel in channels {
+   if !visited.contains(&channel) {
+    let mut path = vec![];
+    find_cycles(&graph, &channel, &mut visited, &mut path, &mut cycles);
+   }
+  }
+  cycles
+ }
+}
+
+/// 深さ優先探索で訪問中の経路 path へ戻る辺を見つけたらループとして記録
+fn find_cycles(
+ graph: &BTreeMap<String, Vec<Edge>>,
+ channel: &str,
+ visited: &mut BTreeSet<String>,
+ path: &mut Vec<(String, String)>,
+ cycles: &mut Vec<String>,
+) {
+ visited.insert(channel.to_string());
+
+ for edge in graph.get(channel).into_iter().flatten() {
+  path.push((channel.to_string(), edge.via.clone()));
+
+  if let Some(start) = path.iter().position(|(c, _)| c == &edge.to) {
+   let cycle = path[start..]
+    .iter()
+    .map(|(c, via)| format!("{} -[{}]-> ", c, via))
+    .collect::<String>();
+   cycles.push(format!("{}{}", cycle, edge.to));
+  } else if !visited.contains(&edge.to) {
+   find_cycles(graph, &edge.to, visited, path, cycles);
+  }
+
+  path.pop();
+ }
+}
@@ -1,4 +1,5 @@
 mod args;
+mod audio;
 mod conf;
 mod error;
 mod logger;
@@ -11,52 +12,69 @@ pub mod web_interface;
 
 pub use crate::{
  args::Args,
+ audio::{
+  AudioBackend, AudioCache, AudioClips, AudioCommand, AudioOutput, AudioQueue, AudioQueueEntry, AudioQueueHook, AudioQueueHooks,
+  AudioQueueReceiver, AudioQueueStatus, AudioQueues, AudioSink, LipSyncTrack, SharedAudioQueue, SharedAudioSink,
+ },
  conf::Conf,
  conf::*,
  error::{Error, Result},
  processor::*,
- state::{ChannelData, ChannelDatum, SharedChannelData, SharedState, State},
+ state::{
+  ChannelData, ChannelDatum, ChannelDatumHop, ChannelDatumMetadata, ChannelEventBus, ChannelEventReceiver, SharedChannelData, SharedState,
+  State,
+ },
 };
 
 use actix_files::Files;
-use rodio::Sink;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 
-pub struct AudioSink(pub Sink);
-pub type SharedAudioSink = Arc<Mutex<AudioSink>>;
-impl std::fmt::Debug for AudioSink {
- fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-  write!(f, "AudioSink")
- }
+pub async fn run() -> Result<()> {
+ run_with_registry(ProcessorRegistry::with_builtin()).await
 }
 
-pub async fn run() -> Result<()> {
+/// 独自の Processor を登録した ProcessorRegistry を使って実行します。
+pub async fn run_with_registry(processor_registry: ProcessorRegistry) -> Result<()> {
  // ロガーの実装を初期化
  logger::init();
 
- // 音声再生用の handle を生成
- // Sink は Shared 化できるが、 steam_handle は Shared 化できないため、
- // ここで actix_web のサービスが .await を抜けるまで保持してしまう。
- let stream = rodio::OutputStreamBuilder::open_default_stream()?;
- let mixer = stream.mixer();
- let audio_sink = Sink::connect_new(mixer);
- let audio_sink = Arc::new(Mutex::new(AudioSink(audio_sink)));
-
  // コマンドライン引数をパースし、ログレベルを更新
  let args = Args::new();
  // conf を必要としない特殊な動作モードを実行
- args.execute_special_modes_without_conf(audio_sink.clone()).await?;
+ args.execute_special_modes_without_conf().await?;
  // 設定を読み込みし、ログレベルを更新
- let conf = Conf::new(&args)?;
+ let mut conf = Conf::new(&args)?;
+ // 再生モードでは記録を上書きしないよう state_data_path を使わない
+ if args.replay.is_some() {
+  log::info!("--replay が指定されたため state_data_path への保存と読み込みは行いません。");
+  conf.state_data_path = None;
+ }
  // conf を必要とする特殊な動作モードを実行
  args.execute_special_modes_with_conf(&conf).await?;
 
  // run_with 機能の実行
  conf.execute_run_with()?;
 
+ // 音声の出力先を生成
+ // Sink は Shared 化できるが、 OutputStream は Shared 化できないため、
+ // ここで actix_web のサービスが .await を抜けるまで保持してしまう。
+ let audio_output = AudioOutput::open(&AudioBackend::new(&conf))?;
+
  // 共有ステートを作成
- let state = State::new(&conf, audio_sink).await?;
+ let state = State::new(&conf, audio_output.audio_sink.clone(), processor_registry).await?;
+ // State を必要とする特殊な動作モードを実行
+ args.execute_special_modes_with_state(&state);
+
+ // 設定ファイルの変更を監視して再読み込み(既存の環境の動作を変えないよう明示的に有効にした場合だけ)
+ if conf.conf_hot_reload.unwrap_or(false) {
+  if let Some(conf_path) = conf.conf_path.clone() {
+   let interval = conf
+    .conf_hot_reload_interval_in_ms
+    .unwrap_or(state::DEFAULT_CONF_HOT_RELOAD_INTERVAL_IN_MS);
+   state::spawn_conf_watcher(state.clone(), conf_path, std::time::Duration::from_millis(interval));
+  }
+ }
 
  // TODO: コード整理
  use twitch_irc::login::StaticLoginCredentials;
@@ -78,11 +96,10 @@ pub async fn run() -> Result<()> {
     twitch_irc::message::ServerMessage::Privmsg(msg) => {
      log::debug!("Received message: {:?}", msg);
      let m = format!("{}:{}", msg.sender.name, msg.message_text);
-     state_for_message_handler
-      .read()
-      .await
-      .push_channel_datum(ChannelDatum::new(ch.clone(), m).with_flag(ChannelDatum::FLAG_IS_FINAL))
-      .await;
+     let cd = ChannelDatum::new(ch.clone(), m)
+      .with_flag(ChannelDatum::FLAG_IS_FINAL)
+      .with_author(&msg.sender.name);
+     state_for_message_handler.read().await.push_channel_datum(cd).await;
     },
     _ => (),
    }
@@ -117,6 +134,9 @@ async fn run_services(conf: Conf, state: SharedState) -> Result<()> {
    .service(web_interface::output::get_index)
    .service(web_interface::output::get_subfile)
    .service(web_interface::status::get)
+   .service(web_interface::audio::get_queue)
+   .service(web_interface::audio::post)
+   .service(web_interface::audio::get_clip)
    .service(web_interface::favicon);
   if let Some(web_ui_resources_path) = conf.web_ui_resources_path {
    app.service(Files::new("/resources", web_ui_resources_path))
@@ -1,104 +1,137 @@
-use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
-use anyhow::{bail, Result};
+use super::{TtsAudio, TtsEngine, TtsProcessor, VoiceMarkup};
+use crate::ProcessorConf;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use std::path::Path;
-use tokio::process::Command;
+use reqwest::Url;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
 
+/// 棒読みちゃんに読み上げを依頼する Processor です。
+pub type Bouyomichan = TtsProcessor<BouyomichanEngine>;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+/// 棒読みちゃんの TCP の既定のポート
+const DEFAULT_TCP_PORT: u16 = 50001;
+/// 棒読みちゃんの HTTP の既定のポート
+const DEFAULT_HTTP_PORT: u16 = 50080;
+
+/// 棒読みちゃんの TCP のコマンド: 読み上げ
+const COMMAND_TALK: i16 = 0x0001;
+/// 棒読みちゃんの TCP の文字コード: UTF-8
+const CHARSET_UTF8: u8 = 0;
+
+#[derive(Debug, Clone)]
+pub struct BouyomichanEngine {
+ endpoint: Endpoint,
+ client: reqwest::Client,
+}
+
+/// 棒読みちゃんの送信先
 #[derive(Debug, Clone)]
-pub struct Bouyomichan {
- conf: SharedProcessorConf,
- channel_data: SharedChannelData,
+enum Endpoint {
+ /// "address:port" へ TCP のバイナリーで送信
+ Tcp(String),
+ /// /talk の URL へ HTTP で送信
+ Http(Url),
 }
 
 #[async_trait]
-impl Processor for Bouyomichan {
+impl TtsEngine for BouyomichanEngine {
  const FEATURE: &'static str = "bouyomichan";
+ const NAME: &'static str = "Bouyomichan";
 
- async fn process(&self, id: u64) -> Result<CompletedAnd> {
-  log::debug!("Bouyomichan::process() が呼び出されました。");
-  // channel_data.read ロック
-  let content = {
-   let channel_data = self.channel_data.read().await;
-   let source = channel_data.iter().rev().find(|cd| cd.get_id() == id);
-   if source.is_none() {
-    bail!("指定された id の ChannelDatum が見つかりませんでした: {}", id);
-   }
-   let source = source.unwrap();
-   // 未確定の入力なら何もしない
-   if !source.has_flag(ChannelDatum::FLAG_IS_FINAL) {
-    log::trace!("未確定の入力なので、処理をスキップします。");
-    return Ok(CompletedAnd::Next);
+ async fn new(pc: &ProcessorConf) -> Result<Self> {
+  let address = pc.address.as_deref().unwrap_or(DEFAULT_ADDRESS);
+
+  // address が http:// または https:// で始まる場合は HTTP 、それ以外は TCP
+  let endpoint = if address.starts_with("http://") || address.starts_with("https://") {
+   let mut url = Url::parse(address).with_context(|| format!("address が URL として不正です: {:?}", address))?;
+   match pc.port {
+    Some(port) => url.set_port(Some(port)),
+    None if url.port().is_none() => url.set_port(Some(DEFAULT_HTTP_PORT)),
+    None => Ok(()),
    }
-   source.content.clone()
+   .map_err(|_| anyhow::anyhow!("address にポートを設定できませんでした: {:?}", address))?;
+   url.set_path("/talk");
+   Endpoint::Http(url)
+  } else {
+   Endpoint::Tcp(format!("{}:{}", address, pc.port.unwrap_or(DEFAULT_TCP_PORT)))
   };
 
-  let conf = self.conf.read().await;
-
-  let mut args = vec![
-   "/T".to_string(),
-   content,
-   conf.speed.unwrap_or(-1).to_string(),
-   conf.tone.unwrap_or(-1).to_string(),
-   conf.volume.unwrap_or(-1).to_string(),
-   conf.voice.unwrap_or(0).to_string(),
-  ];
-  match (&conf.address, conf.port) {
-   (Some(ip), Some(port)) => args.extend(vec![ip.clone(), port.to_string()]),
-   _ => (),
-  }
-
-  let command = conf.remote_talk_path.as_ref().unwrap();
-
-  log::debug!("棒読みちゃんにリクエストを送信します。command = {:?}, args = {:?}", command, args);
-  Command::new(command).args(args).spawn()?;
-
-  Ok(CompletedAnd::Next)
+  Ok(BouyomichanEngine {
+   endpoint,
+   client: reqwest::Client::new(),
+  })
  }
 
- fn conf(&self) -> SharedProcessorConf {
-  self.conf.clone()
- }
+ async fn synthesize(&self, text: &str, conf: &ProcessorConf) -> Result<TtsAudio> {
+  let speed = conf.speed.unwrap_or(-1);
+  let tone = conf.tone.unwrap_or(-1);
+  let volume = conf.volume.unwrap_or(-1);
+  let voice = conf.voice.unwrap_or(0);
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
-  let mut p = Bouyomichan {
-   conf: pc.as_shared(),
-   channel_data: state.read().await.channel_data.clone(),
-  };
-
-  if !p.is_established().await {
-   bail!("Bouyomichan が正常に設定されていません: {:?}", pc);
+  match &self.endpoint {
+   Endpoint::Tcp(address) => {
+    log::debug!("棒読みちゃんに TCP でリクエストを送信します。address = {:?}", address);
+    let packet = talk_packet(text, speed, tone, volume, voice);
+    let mut stream = TcpStream::connect(address).await?;
+    stream.write_all(&packet).await?;
+    stream.shutdown().await?;
+   },
+   Endpoint::Http(url) => {
+    log::debug!("棒読みちゃんに HTTP でリクエストを送信します。url = {}", url);
+    self
+     .client
+     .get(url.clone())
+     .query(&[
+      ("text", text.to_string()),
+      ("speed", speed.to_string()),
+      ("tone", tone.to_string()),
+      ("volume", volume.to_string()),
+      ("voice", voice.to_string()),
+     ])
+     .send()
+     .await?
+     .error_for_status()?;
+   },
   }
 
-  Ok(ProcessorKind::Bouyomichan(p))
+  Ok(TtsAudio::Delegated)
  }
 
- async fn is_channel_from(&self, channel_from: &str) -> bool {
-  let conf = self.conf.read().await;
-  conf.channel_from.as_ref().unwrap() == channel_from
+ fn returns_audio(&self) -> bool {
+  false
  }
 
- async fn is_established(&mut self) -> bool {
-  let conf = self.conf.read().await;
+ async fn apply_markup(&self, pc: &mut ProcessorConf, markup: &VoiceMarkup) -> Result<()> {
+  // 棒読みちゃんの速度と音量は 100 が標準なので倍率を 100 倍して使う
+  if let Some(speed) = markup.speed {
+   pc.speed = Some((speed * 100.0).round() as i16);
+  }
+  if let Some(volume) = markup.volume {
+   pc.volume = Some((volume * 100.0).round() as i16);
+  }
+  if let Some(voice) = markup.speaker.as_ref().or(markup.style.as_ref()) {
+   pc.voice = Some(
+    voice
+     .parse()
+     .with_context(|| format!("棒読みちゃんの声の種類は数値で指定してください: {:?}", voice))?,
+   );
+  }
+  Ok(())
+ }
 
-  if conf.channel_from.is_none() {
-   log::error!("channel_from が設定されていません。");
-   return false;
+ async fn is_established(&mut self, conf: &ProcessorConf) -> bool {
+  if conf.remote_talk_path.is_some() {
+   log::warn!("remote_talk_path は不要になりました。 RemoteTalk.exe を使わずに address, port の棒読みちゃんへ直接送信します。");
   }
-  match conf.remote_talk_path {
-   Some(ref path) if !Path::new(path).exists() => {
-    log::error!("指定されたコマンドが存在しません: {}", path);
-    return false;
-   },
-   None => {
-    log::error!("remote_talk_path が設定されていません。");
-    return false;
-   },
-   _ => {},
+  if conf.speech_event_channel.is_some() {
+   log::warn!("棒読みちゃんは再生の開始や終了が分からないため speech_event_channel は使えません。");
   }
   log::info!(
-   "Bouyomichan は正常に設定されています: channel: {:?} speed: {:?} tone: {:?} volume: {:?} voice: {:?}",
+   "Bouyomichan は正常に設定されています: channel: {:?} endpoint: {:?} speed: {:?} tone: {:?} volume: {:?} voice: {:?}",
    conf.channel_from,
+   self.endpoint,
    conf.speed,
    conf.tone,
    conf.volume,
@@ -107,3 +140,68 @@ impl Processor for Bouyomichan {
   true
  }
 }
+
+/// 棒読みちゃんの TCP の読み上げコマンドのバイナリーを作ります。数値は全てリトルエンディアンです。
+/// command(i16) speed(i16) tone(i16) volume(i16) voice(i16) charset(u8) length(i32) message(UTF-8)
+fn talk_packet(text: &str, speed: i16, tone: i16, volume: i16, voice: i16) -> Vec<u8> {
+ let message = text.as_bytes();
+ let mut packet = Vec::with_capacity(15 + message.len());
+ packet.extend_from_slice(&COMMAND_TALK.to_le_bytes());
+ packet.extend_from_slice(&speed.to_le_bytes());
+ packet.extend_from_slice(&tone.to_le_bytes());
+ packet.extend_from_slice(&volume.to_le_bytes());
+ packet.extend_from_slice(&voice.to_le_bytes());
+ packet.push(CHARSET_UTF8);
+ packet.extend_from_slice(&(message.len() as i32).to_le_bytes());
+ packet.extend_from_slice(message);
+ packet
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use tokio::io::AsyncReadExt;
+ use tokio::net::TcpListener;
+
+ #[test]
+ fn talk_packet_is_little_endian_with_utf8_message() {
+  let packet = talk_packet("あa", 68, 133, -1, 2);
+  let mut expected = vec![
+   0x01, 0x00, // command
+   0x44, 0x00, // speed
+   0x85, 0x00, // tone
+   0xff, 0xff, // volume
+   0x02, 0x00, // voice
+   0x00, // charset
+   0x04, 0x00, 0x00, 0x00, // length
+  ];
+  expected.extend_from_slice("あa".as_bytes());
+  assert_eq!(packet, expected);
+ }
+
+ #[tokio::test]
+ async fn synthesize_sends_talk_packet_over_tcp() {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let received = tokio::spawn(async move {
+   let (mut stream, _) = listener.accept().await.unwrap();
+   let mut received = vec![];
+   stream.read_to_end(&mut received).await.unwrap();
+   received
+  });
+
+  let pc = ProcessorConf {
+   address: Some("127.0.0.1".to_string()),
+   port: Some(port),
+   speed: Some(100),
+   voice: Some(1),
+   ..Default::default()
+  };
+  let engine = BouyomichanEngine::new(&pc).await.unwrap();
+  let audio = engine.synthesize("こんにちは", &pc).await.unwrap();
+  assert!(matches!(audio, TtsAudio::Delegated));
+
+  // 設定の無い tone, volume は棒読みちゃんの標準を表す -1
+  assert_eq!(received.await.unwrap(), talk_packet("こんにちは", 100, -1, -1, 1));
+ }
+}
@@ -1,22 +1,23 @@
-use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedAudioSink, SharedChannelData, SharedProcessorConf, SharedState};
-use anyhow::{bail, Result};
+use super::{SpeakersCache, TtsAudio, TtsEngine, TtsProcessor, VoiceMarkup};
+use crate::{Arc, ProcessorConf, RwLock};
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use regex::Regex;
-use rodio::Decoder;
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
-use std::path::PathBuf;
+
+/// CoeiroInk の API で音声合成する Processor です。
+pub type CoeiroInk = TtsProcessor<CoeiroInkEngine>;
+
+/// CoeiroInk の既定の API の URL
+pub const DEFAULT_COEIROINK_API_URL: &str = "http://localhost:50032/v1/predict";
 
 #[derive(Debug, Clone)]
-pub struct CoeiroInk {
- conf: SharedProcessorConf,
- channel_data: SharedChannelData,
+pub struct CoeiroInkEngine {
  synthesis_or_predict_request_url: String,
- synthesis_or_predict_request_template: SynthesisOrPredictRequest,
- audio_file_store_path: Option<String>,
- split_regex: Option<Regex>,
- audio_sink: SharedAudioSink,
+ /// speaker_name, style_name を CoeiroInk の再起動などで再解決した (speaker_uuid, style_id)
+ resolved_voice: Arc<RwLock<Option<(String, i64)>>>,
+ /// 読み上げのタグで話者やスタイルを名前から探すための /v1/speakers の一覧
+ speakers: SpeakersCache<Speakers>,
+ client: reqwest::Client,
 }
 
 const DEFAULT_VOLUME_SCALE: f64 = 1.00;
@@ -27,149 +28,24 @@ const DEFAULT_POST_PHONEME_LENGTH: f64 = 0.10;
 const DEFAULT_OUTPUT_SAMPLING_RATE: u32 = 48000;
 
 #[async_trait]
-impl Processor for CoeiroInk {
+impl TtsEngine for CoeiroInkEngine {
  const FEATURE: &'static str = "coeiroink";
+ const NAME: &'static str = "CoeiroInk";
 
- async fn process(&self, id: u64) -> Result<CompletedAnd> {
-  log::debug!("CoeiroInk::process() が呼び出されました。");
-
-  let channel_data = self.channel_data.clone();
-  let synthesis_or_predict_request_template = self.synthesis_or_predict_request_template.clone();
-  let synthesis_or_predict_request_url = self.synthesis_or_predict_request_url.clone();
-  let audio_file_store_path = self.audio_file_store_path.clone();
-  let split_regex = self.split_regex.clone();
-  let audio_sink = self.audio_sink.clone();
-
-  tokio::spawn(async move {
-   // 入力を取得
-   let source = {
-    let channel_data = channel_data.read().await;
-    match channel_data.iter().rev().find(|cd| cd.get_id() == id) {
-     Some(source) if source.has_flag(ChannelDatum::FLAG_IS_FINAL) => source.content.clone(),
-     Some(_) => {
-      log::trace!("未確定の入力なので、処理をスキップします。");
-      return Ok(());
-     },
-     None => bail!("指定された id の ChannelDatum が見つかりませんでした: {}", id),
-    }
-   };
-
-   // split_sentence による分割
-   let sources = match split_regex {
-    // 正規表現で分割
-    Some(split_regex) => split_regex
-     .split(&source)
-     .map(|s| s.trim().to_string())
-     .filter(|s| !s.is_empty())
-     .collect::<Vec<String>>(),
-    // 分割しない
-    _ => vec![source],
-   };
-   let sources_len = sources.len();
-
-   let locked_audio_sink = audio_sink.lock().await;
-
-   for (num, source) in sources.into_iter().enumerate() {
-    let path = audio_file_store_path
-     .as_ref()
-     .map(|p| format!("{}_{}_{}.wav", &p, num, sources_len));
-
-    // CoeiroInk に音声合成をリクエスト -> WAV ペイロードを取得
-    log::debug!("CoeiroInk に音声合成をリクエストします。 {} / {}", num + 1, sources_len);
-    let request_payload = synthesis_or_predict_request_template.build_with_text(source);
-    let response = reqwest::Client::new()
-     .post(&synthesis_or_predict_request_url)
-     .json(&request_payload)
-     .send()
-     .await;
-
-    let response = match response {
-     Ok(response) => response,
-     Err(e) => {
-      log::error!("CoeiroInk への音声合成リクエストに失敗しました: {:?}", e);
-      continue;
-     },
-    };
-
-    let audio_data = response.bytes().await?;
-    log::debug!("CoeiroInk からの音声合成データの取得に成功しました。");
-
-    if let Some(path) = path {
-     // path に {T} が含まれていたら ISO8601 日時文字列から : と - を除去して置換
-     let path = match path.contains("{T}") {
-      true => {
-       let t = chrono::Utc::now().to_rfc3339().replace(":", "").replace("-", "");
-       let path = std::path::Path::new(&path.replace("{T}", &t)).to_path_buf();
-       path
-      },
-      false => PathBuf::from(path),
-     };
-
-     log::debug!("CoeiroInk からの音声合成データを {} に保存します。", path.display());
-     tokio::fs::write(path, audio_data.clone()).await?;
-     log::trace!("CoeiroInk からの音声合成データを保存しました。");
-    }
-
-    // ペイロードを WAV として再生
-    let cursor = Cursor::new(audio_data);
-    let source = Decoder::new(cursor)?;
-
-    locked_audio_sink.0.append(source);
-
-    log::debug!(
-     "CoeiroInk からの音声合成データを再生シンクへ送出しました。 {} / {}",
-     num + 1,
-     sources_len
-    );
-   }
-
-   Ok(())
-  });
-
-  log::trace!("CoeiroInk::process() は非同期処理を開始しました。");
-
-  Ok(CompletedAnd::Next)
- }
-
- fn conf(&self) -> SharedProcessorConf {
-  self.conf.clone()
+ async fn fix_conf(pc: &ProcessorConf) -> Result<ProcessorConf> {
+  fix_conf(pc).await
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
-  let pc = fix_conf(pc).await?;
-
-  let mut p = CoeiroInk {
-   conf: pc.as_shared(),
-   channel_data: state.read().await.channel_data.clone(),
+ async fn new(pc: &ProcessorConf) -> Result<Self> {
+  Ok(CoeiroInkEngine {
    synthesis_or_predict_request_url: pc.api_url.clone().unwrap(),
-   synthesis_or_predict_request_template: SynthesisOrPredictRequest::default(),
-   audio_file_store_path: pc.audio_file_store_path.clone(),
-   split_regex: pc.split_regex_pattern.as_ref().map(|s| Regex::new(s).unwrap()),
-   audio_sink: state.read().await.audio_sink.clone(),
-  };
-
-  p.update_template().await;
-
-  if !p.is_established().await {
-   bail!("CoeiroInk が正常に設定されていません: {:?}", pc);
-  }
-
-  Ok(ProcessorKind::CoeiroInk(p))
- }
-
- async fn is_channel_from(&self, channel_from: &str) -> bool {
-  let conf = self.conf.read().await;
-  conf.channel_from.as_ref().unwrap() == channel_from
+   resolved_voice: Arc::new(RwLock::new(None)),
+   speakers: SpeakersCache::default(),
+   client: reqwest::Client::new(),
+  })
  }
 
- async fn is_established(&mut self) -> bool {
-  let conf = self.conf.read().await;
-
-  if conf.channel_from.is_none() {
-   log::error!("channel_from が設定されていません。");
-   return false;
-  }
-
+ async fn is_established(&mut self, conf: &ProcessorConf) -> bool {
   log::info!(
    "CoeiroInk は正常に設定されています: channel: {:?} speed: {:?} tone: {:?} volume: {:?} voice: {:?}",
    conf.channel_from,
@@ -180,51 +56,137 @@ impl Processor for CoeiroInk {
   );
   true
  }
+
+ async fn synthesize(&self, text: &str, pc: &ProcessorConf) -> Result<TtsAudio> {
+  // CoeiroInk に音声合成をリクエスト -> WAV ペイロードを取得
+  // 読み上げのタグで話者やスタイルが上書きされている場合があるため pc から組み立てる
+  let mut request_payload = SynthesisOrPredictRequest::new(pc).build_with_text(text.to_string());
+
+  // speaker_name, style_name で指定されている場合は再解決した ID があればそれを使う
+  let is_named = pc.speaker_name.is_some() || pc.style_name.is_some();
+  if is_named {
+   if let Some((speaker_uuid, style_id)) = self.resolved_voice.read().await.clone() {
+    request_payload.speakerUuid = speaker_uuid;
+    request_payload.styleId = style_id;
+   }
+  }
+
+  match self.request(&request_payload).await {
+   // CoeiroInk の再起動などで ID が変わっている場合があるため名前から再解決して再試行
+   Err(e) if is_named => {
+    log::warn!(
+     "CoeiroInk の音声合成に失敗したため speaker_name, style_name を再解決して再試行します: {:?}",
+     e
+    );
+    let (speaker_uuid, style_id) = resolve_voice(pc).await?;
+    log::info!(
+     "CoeiroInk の話者とスタイルを再解決しました: speaker_uuid: {} style_id: {}",
+     speaker_uuid,
+     style_id
+    );
+    *self.resolved_voice.write().await = Some((speaker_uuid.clone(), style_id));
+    request_payload.speakerUuid = speaker_uuid;
+    request_payload.styleId = style_id;
+    self.request(&request_payload).await
+   },
+   result => result,
+  }
+ }
+
+ async fn apply_markup(&self, pc: &mut ProcessorConf, markup: &VoiceMarkup) -> Result<()> {
+  markup.apply_scales(pc);
+  if markup.speaker.is_none() && markup.style.is_none() {
+   return Ok(());
+  }
+
+  // 話者やスタイルは名前でも指定できるよう /v1/speakers から探す
+  // /v1/speakers は立ち絵の画像なども含み大きいため、しばらく使い回す
+  let speakers = self
+   .speakers
+   .get_or_fetch(CoeiroInk::get_speakers(&self.synthesis_or_predict_request_url))
+   .await?;
+  let speaker = match markup.speaker.as_ref() {
+   Some(speaker) => speakers
+    .iter()
+    .find(|s| &s.speakerUuid == speaker || &s.speakerName == speaker)
+    .with_context(|| format!("speaker の話者が見つかりませんでした: {:?}", speaker))?,
+   None => speakers
+    .iter()
+    .find(|s| Some(&s.speakerUuid) == pc.speaker_uuid.as_ref())
+    .with_context(|| format!("speaker_uuid の話者が見つかりませんでした: {:?}", pc.speaker_uuid))?,
+  };
+  let style = match markup.style.as_ref() {
+   Some(style) => speaker
+    .styles
+    .iter()
+    .find(|s| &s.styleId.to_string() == style || &s.styleName == style)
+    .with_context(|| format!("{} に style のスタイルが見つかりませんでした: {:?}", speaker.speakerName, style))?,
+   None => speaker
+    .styles
+    .first()
+    .with_context(|| format!("{} にスタイルが存在しませんでした。", speaker.speakerName))?,
+  };
+  pc.speaker_uuid = Some(speaker.speakerUuid.clone());
+  pc.style_id = Some(style.styleId);
+  // タグで指定した ID を speaker_name, style_name の解決より優先
+  pc.speaker_name = None;
+  pc.style_name = None;
+  Ok(())
+ }
+
+ fn voice_key(&self, pc: &ProcessorConf) -> Option<String> {
+  let template = serde_json::to_string(&SynthesisOrPredictRequest::new(pc)).ok()?;
+  // 名前で指定されている場合は再解決で ID が変わっても同じ声
+  Some(format!(
+   "{} {:?} {:?} {}",
+   self.synthesis_or_predict_request_url, pc.speaker_name, pc.style_name, template
+  ))
+ }
+}
+
+impl CoeiroInkEngine {
+ async fn request(&self, request_payload: &SynthesisOrPredictRequest) -> Result<TtsAudio> {
+  let response = self
+   .client
+   .post(&self.synthesis_or_predict_request_url)
+   .json(request_payload)
+   .send()
+   .await?
+   .error_for_status()?;
+  let audio_data = response.bytes().await?;
+  Ok(TtsAudio::Data(audio_data.to_vec()))
+ }
 }
 
 async fn fix_conf(original: &ProcessorConf) -> Result<ProcessorConf> {
  let mut fixed = original.clone();
 
  if fixed.api_url.is_none() {
-  log::warn!("api_url が設定されていないため、 http://localhost:50032/v1/predict にデフォルトします。");
-  fixed.api_url = Some("http://localhost:50032/v1/predict".to_string());
+  log::warn!(
+   "api_url が設定されていないため、 {} にデフォルトします。",
+   DEFAULT_COEIROINK_API_URL
+  );
+  fixed.api_url = Some(DEFAULT_COEIROINK_API_URL.to_string());
  }
 
- if fixed.speaker_uuid.is_none() {
-  log::warn!("speaker_uuid が設定されていません。 API でデフォルトロードを試みます。");
-  match CoeiroInk::get_speakers().await {
-   Ok(speakers) if !speakers.is_empty() => {
-    log::warn!(
-     "speaker_uuid を {} ( {} ) にデフォルトします。",
-     speakers[0].speakerUuid,
-     speakers[0].speakerName
-    );
-    fixed.speaker_uuid = Some(speakers[0].speakerUuid.clone());
-   },
-   _ => bail!("CoeiroInk と API 通信できなかったためデフォルトロードに失敗しました。CoeiroInk の動作状態を確認してください。"),
+ // speaker_name, style_name または設定されていない speaker_uuid, style_id を API で解決
+ if fixed.speaker_name.is_some() || fixed.style_name.is_some() || fixed.speaker_uuid.is_none() || fixed.style_id.is_none() {
+  if fixed.speaker_uuid.is_none() && fixed.speaker_name.is_none() {
+   log::warn!("speaker_uuid が設定されていません。 API でデフォルトロードを試みます。");
   }
- }
-
- if fixed.style_id.is_none() {
-  log::warn!("style_id が設定されていません。 API でデフォルトロードを試みます。");
-  match CoeiroInk::get_speakers().await {
-   Ok(speakers) if !speakers.is_empty() => {
-    let speaker = speakers
-     .into_iter()
-     .find(|s| s.speakerUuid.eq(fixed.speaker_uuid.as_ref().unwrap()));
-    if speaker.is_none() {
-     bail!("speaker_uuid の Speaker が CoeiroInk の応答に含まれませんでした。");
-    }
-    let style_id = speaker.unwrap().styles.iter().next().cloned();
-    if style_id.is_none() {
-     bail!("speaker_uuid の Speaker に Style 情報が存在しませんでした。");
-    }
-    let style = style_id.unwrap();
-    log::warn!("style_id を {}: {} にデフォルトします。", style.styleId, style.styleName);
-    fixed.style_id = Some(style.styleId);
-   },
-   _ => bail!("CoeiroInk と API 通信できなかったためデフォルトロードに失敗しました。CoeiroInk の動作状態を確認してください。"),
+  if fixed.style_id.is_none() && fixed.style_name.is_none() {
+   log::warn!("style_id が設定されていません。 API でデフォルトロードを試みます。");
   }
+  let (speaker_uuid, style_id) = resolve_voice(&fixed).await?;
+  log::info!(
+   "CoeiroInk の話者とスタイルを解決しました: speaker_name: {:?} style_name: {:?} -> speaker_uuid: {} style_id: {}",
+   fixed.speaker_name,
+   fixed.style_name,
+   speaker_uuid,
+   style_id
+  );
+  fixed.speaker_uuid = Some(speaker_uuid);
+  fixed.style_id = Some(style_id);
  }
  if fixed.speed_scale.is_none() {
   log::warn!("speed_scale が設定されていないため 1.00 にデフォルトします。");
@@ -301,13 +263,69 @@ pub struct SpeakerStyle {
 pub type Speakers = Vec<Speaker>;
 
 impl CoeiroInk {
- pub async fn get_speakers() -> Result<Speakers> {
-  let url = "http://127.0.0.1:50032/v1/speakers";
-  let res = reqwest::get(url).await?;
+ /// api_url (http://localhost:50032/v1/predict など) の /v1/ より前をベースの URL (http://localhost:50032) とします。
+ pub fn base_url(api_url: &str) -> String {
+  let api_url = api_url.trim_end_matches('/');
+  match api_url.find("/v1/") {
+   Some(index) => api_url[..index].to_string(),
+   None => api_url.to_string(),
+  }
+ }
+
+ /// api_url の CoeiroInk から /v1/speakers で話者とスタイルの一覧を取得します。
+ pub async fn get_speakers(api_url: &str) -> Result<Speakers> {
+  let url = format!("{}/v1/speakers", CoeiroInk::base_url(api_url));
+  let res = reqwest::get(url).await?.error_for_status()?;
   Ok(res.json::<Speakers>().await?)
  }
 }
 
+/// speaker_name, style_name を api_url の CoeiroInk の API で (speaker_uuid, style_id) へ解決します。
+/// 名前は完全に一致するものが無ければ部分一致で探します。名前が設定されていなければ speaker_uuid, style_id を使い、それも無ければ最初の話者やスタイルにします。
+async fn resolve_voice(pc: &ProcessorConf) -> Result<(String, i64)> {
+ let speakers = CoeiroInk::get_speakers(pc.api_url.as_ref().unwrap())
+  .await
+  .with_context(|| "CoeiroInk と API 通信できませんでした。CoeiroInk の動作状態を確認してください。")?;
+
+ let speaker = match (pc.speaker_name.as_ref(), pc.speaker_uuid.as_ref()) {
+  (Some(name), _) => find_by_name(&speakers, name, |s| &s.speakerName)
+   .with_context(|| format!("speaker_name の Speaker が CoeiroInk の応答に含まれませんでした: {:?}", name))?,
+  (None, Some(uuid)) => speakers
+   .iter()
+   .find(|s| &s.speakerUuid == uuid)
+   .with_context(|| format!("speaker_uuid の Speaker が CoeiroInk の応答に含まれませんでした: {:?}", uuid))?,
+  (None, None) => speakers
+   .first()
+   .with_context(|| "CoeiroInk の応答に Speaker が存在しませんでした。")?,
+ };
+
+ let style_id = match (pc.style_name.as_ref(), pc.style_id) {
+  (Some(name), _) => {
+   find_by_name(&speaker.styles, name, |s| &s.styleName)
+    .with_context(|| format!("{} に style_name の Style が存在しませんでした: {:?}", speaker.speakerName, name))?
+    .styleId
+  },
+  (None, Some(style_id)) => style_id,
+  (None, None) => {
+   speaker
+    .styles
+    .first()
+    .with_context(|| format!("{} に Style 情報が存在しませんでした。", speaker.speakerName))?
+    .styleId
+  },
+ };
+
+ Ok((speaker.speakerUuid.clone(), style_id))
+}
+
+/// name と完全に一致するもの、無ければ name を含むものを探します。
+fn find_by_name<'a, T>(items: &'a [T], name: &str, get_name: impl Fn(&T) -> &String) -> Option<&'a T> {
+ items
+  .iter()
+  .find(|item| get_name(item) == name)
+  .or_else(|| items.iter().find(|item| get_name(item).contains(name)))
+}
+
 // JSON API の仕様にあわせるため、フィールド名はcamelCaseで定義する
 // このため警告を無効化するために #[allow(non_snake_case)] を付与する
 #[allow(non_snake_case)]
@@ -327,26 +345,26 @@ pub struct SynthesisOrPredictRequest {
 }
 
 impl SynthesisOrPredictRequest {
+ fn new(conf: &ProcessorConf) -> Self {
+  SynthesisOrPredictRequest {
+   // for predict & synthesis
+   speakerUuid: conf.speaker_uuid.clone().unwrap(),
+   styleId: conf.style_id.unwrap(),
+   speedScale: conf.speed_scale.unwrap(),
+   // for synthesis
+   volumeScale: conf.volume_scale.unwrap_or(DEFAULT_VOLUME_SCALE),
+   pitchScale: conf.pitch_scale.unwrap_or(DEFAULT_PITCH_SCALE),
+   intonationScale: conf.intonation_scale.unwrap_or(DEFAULT_INTONATION_SCALE),
+   prePhonemeLength: conf.pre_phoneme_length.unwrap_or(DEFAULT_PRE_PHONEME_LENGTH),
+   postPhonemeLength: conf.post_phoneme_length.unwrap_or(DEFAULT_POST_PHONEME_LENGTH),
+   outputSamplingRate: conf.output_sampling_rate.unwrap_or(DEFAULT_OUTPUT_SAMPLING_RATE),
+   ..Default::default()
+  }
+ }
+
  fn build_with_text(&self, text: String) -> Self {
   let mut cloned = self.clone();
   cloned.text = text;
   cloned
  }
 }
-
-impl CoeiroInk {
- async fn update_template(&mut self) {
-  let conf = self.conf.read().await;
-  // for predict & synthesis
-  self.synthesis_or_predict_request_template.speakerUuid = conf.speaker_uuid.clone().unwrap();
-  self.synthesis_or_predict_request_template.styleId = conf.style_id.unwrap();
-  self.synthesis_or_predict_request_template.speedScale = conf.speed_scale.unwrap();
-  // for synthesis
-  self.synthesis_or_predict_request_template.volumeScale = conf.volume_scale.unwrap_or(DEFAULT_VOLUME_SCALE);
-  self.synthesis_or_predict_request_template.pitchScale = conf.pitch_scale.unwrap_or(DEFAULT_PITCH_SCALE);
-  self.synthesis_or_predict_request_template.intonationScale = conf.intonation_scale.unwrap_or(DEFAULT_INTONATION_SCALE);
-  self.synthesis_or_predict_request_template.prePhonemeLength = conf.pre_phoneme_length.unwrap_or(DEFAULT_PRE_PHONEME_LENGTH);
-  self.synthesis_or_predict_request_template.postPhonemeLength = conf.post_phoneme_length.unwrap_or(DEFAULT_POST_PHONEME_LENGTH);
-  self.synthesis_or_predict_request_template.outputSamplingRate = conf.output_sampling_rate.unwrap_or(DEFAULT_OUTPUT_SAMPLING_RATE);
- }
-}
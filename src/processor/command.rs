@@ -1,6 +1,6 @@
 use super::{CompletedAnd, Processor};
 use crate::conf::CommandSet;
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
+use crate::{ChannelDatum, ProcessorConf, SharedChannelData, SharedProcessorConf, SharedState, State};
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 use std::collections::VecDeque;
@@ -23,10 +23,10 @@ impl Processor for Command {
   let conf = self.conf.read().await.clone();
 
   // 入力を取得
-  let content = {
+  let source = {
    let channel_data = self.channel_data.read().await;
    match channel_data.iter().rev().find(|cd| cd.get_id() == id) {
-    Some(source) if source.has_flag(ChannelDatum::FLAG_IS_FINAL) => source.content.clone(),
+    Some(source) if source.has_flag(ChannelDatum::FLAG_IS_FINAL) => source.clone(),
     Some(_) => {
      log::trace!("未確定の入力なので、処理をスキップします。");
      return Ok(self.if_not_command);
@@ -35,12 +35,12 @@ impl Processor for Command {
    }
   };
 
-  if !content.starts_with("/") {
-   log::debug!("コマンドではないので、処理をスキップします: {:?}", content);
+  if !source.content.starts_with("/") {
+   log::debug!("コマンドではないので、処理をスキップします: {:?}", source.content);
    return Ok(self.if_not_command);
   }
 
-  let mut args = content.trim_start_matches("/").split(" ").collect::<VecDeque<&str>>();
+  let mut args = source.content.trim_start_matches("/").split(" ").collect::<VecDeque<&str>>();
   let command = args.pop_front().unwrap_or_default();
 
   match command {
@@ -50,21 +50,49 @@ impl Processor for Command {
    },
    "disable" if args.len() >= 1 => {
     log::info!("disable がコマンドされたので group = {} の Processor を無効化します。", args[0]);
-    response1(
-     conf,
-     self.state.clone(),
-     "disable",
-     "group = {A} の Processor を無効化しました。",
-     args[0],
-    )
-    .await;
+    let count = self.state.read().await.set_processor_group_enabled(args[0], false).await;
+    if count == 0 {
+     log::warn!("group = {} に属する Processor が見つかりませんでした。", args[0]);
+     response1(
+      conf,
+      self.state.clone(),
+      &source,
+      "disable:not-found",
+      "group = {A} の Processor が見つかりませんでした。",
+      args[0],
+     )
+     .await;
+    } else {
+     response1(
+      conf,
+      self.state.clone(),
+      &source,
+      "disable",
+      "group = {A} の Processor を無効化しました。",
+      args[0],
+     )
+     .await;
+    }
    },
-   "enable" => {
-    if args.len() >= 1 {
-     log::info!("enable がコマンドされたので group = {} の Processor を有効化します。", args[0]);
+   "enable" if args.len() >= 1 => {
+    log::info!("enable がコマンドされたので group = {} の Processor を有効化します。", args[0]);
+    let count = self.state.read().await.set_processor_group_enabled(args[0], true).await;
+    if count == 0 {
+     log::warn!("group = {} に属する Processor が見つかりませんでした。", args[0]);
      response1(
       conf,
       self.state.clone(),
+      &source,
+      "enable:not-found",
+      "group = {A} の Processor が見つかりませんでした。",
+      args[0],
+     )
+     .await;
+    } else {
+     response1(
+      conf,
+      self.state.clone(),
+      &source,
       "enable",
       "group = {A} の Processor を有効化しました。",
       args[0],
@@ -72,35 +100,165 @@ impl Processor for Command {
      .await;
     }
    },
-   "reload" => {
-    log::info!("reload がコマンドされたので Virtual Avatar Connect は設定を再読み込みします。");
+   "reload" if args.len() >= 1 => {
+    log::info!(
+     "reload がコマンドされたので group = {} の Processor の設定を再読み込みします。",
+     args[0]
+    );
+    // Processor 群の入れ替えには State の書き込みロックが必要なため、この入力の処理が終わってから実行
+    let state = self.state.clone();
+    let group = args[0].to_string();
+    tokio::spawn(async move {
+     match State::reload_processor_group(&state, &group).await {
+      Ok(count) => {
+       log::info!("group = {} の Processor {} 個を再読み込みしました。", group, count);
+       response1(
+        conf,
+        state,
+        &source,
+        "reload",
+        "group = {A} の Processor の設定を再読み込みしました。",
+        &group,
+       )
+       .await;
+      },
+      Err(e) => {
+       log::error!("group = {} の Processor の再読み込みに失敗しました: {:?}", group, e);
+       response1(
+        conf,
+        state,
+        &source,
+        "reload:error",
+        "group = {A} の Processor の設定の再読み込みに失敗しました。",
+        &group,
+       )
+       .await;
+      },
+     }
+    });
+   },
+   "skip" => {
+    let audio_queues = self.state.read().await.audio_queues.clone();
+    let id = match args.front().map(|a| a.parse::<u64>()) {
+     Some(Ok(id)) => Some(id),
+     Some(Err(_)) => {
+      log::warn!("skip の引数は再生キューの ID を数値で指定して下さい: {:?}", args[0]);
+      response1(
+       conf,
+       self.state.clone(),
+       &source,
+       "skip:not-found",
+       "{A}の音声が見つかりませんでした。",
+       args[0],
+      )
+      .await;
+      return Ok(CompletedAnd::Break);
+     },
+     None => None,
+    };
+    log::info!("skip がコマンドされたので音声を飛ばします: id = {:?}", id);
+    let target = id.map(|id| format!("ID {} ", id)).unwrap_or_else(|| "再生中".to_string());
+    if audio_queues.skip(id).await {
+     response1(conf, self.state.clone(), &source, "skip", "{A}の音声を飛ばしました。", &target).await;
+    } else {
+     response1(
+      conf,
+      self.state.clone(),
+      &source,
+      "skip:not-found",
+      "{A}の音声が見つかりませんでした。",
+      &target,
+     )
+     .await;
+    }
+   },
+   "stop" => {
+    log::info!("stop がコマンドされたので音声の再生を止め、再生待ちの音声を破棄します。");
+    let audio_queues = self.state.read().await.audio_queues.clone();
+    let count = audio_queues.stop().await;
     response1(
      conf,
      self.state.clone(),
-     "reload",
-     "group = {A} の Processor の設定を再読み込みしました。",
-     args[0],
+     &source,
+     "stop",
+     "音声 {A} 件の再生を止めました。",
+     &count.to_string(),
     )
     .await;
    },
-   "set" if args.len() >= 1 => {
-    log::info!("set がコマンドされセット名 {:?} の実行が試行されます。", args[0]);
-    response1(conf.clone(), self.state.clone(), "set", "セット {A} の実行を試みます。", args[0]).await;
-    if let Err(e) = activate_command_set(args[0], &conf.set, self.state.clone()).await {
-      log::error!("セットの実行中にエラーが発生しました: {:?}", e);
+   "clear-audio" => {
+    log::info!("clear-audio がコマンドされたので再生待ちの音声を破棄します。");
+    let audio_queues = self.state.read().await.audio_queues.clone();
+    let count = audio_queues.clear().await;
+    response1(
+     conf,
+     self.state.clone(),
+     &source,
+     "clear-audio",
+     "再生待ちの音声 {A} 件を破棄しました。",
+     &count.to_string(),
+    )
+    .await;
+   },
+   "volume" => {
+    let audio_queues = self.state.read().await.audio_queues.clone();
+    let volume = match args.front().map(|a| a.trim_end_matches('%').parse::<f32>()) {
+     Some(Ok(volume)) => {
+      log::info!("volume がコマンドされたので音量を {}% に設定します。", volume);
+      audio_queues.set_volume(volume).await
+     },
+     Some(Err(_)) => {
+      log::warn!("volume の引数は音量を % の数値で指定して下さい: {:?}", args[0]);
       response1(
        conf,
        self.state.clone(),
-       "set:error",
-       "セット {A} の実行中にエラーが発生しました。",
+       &source,
+       "volume:error",
+       "音量 {A} は数値ではありません。",
        args[0],
       )
       .await;
+      return Ok(CompletedAnd::Break);
+     },
+     None => audio_queues.volume().await,
+    };
+    response1(
+     conf,
+     self.state.clone(),
+     &source,
+     "volume",
+     "音量は {A}% です。",
+     &volume.round().to_string(),
+    )
+    .await;
+   },
+   "set" if args.len() >= 1 => {
+    log::info!("set がコマンドされセット名 {:?} の実行が試行されます。", args[0]);
+    response1(
+     conf.clone(),
+     self.state.clone(),
+     &source,
+     "set",
+     "セット {A} の実行を試みます。",
+     args[0],
+    )
+    .await;
+    if let Err(e) = activate_command_set(args[0], &conf.set, self.state.clone(), &source, &conf.get_id()).await {
+     log::error!("セットの実行中にエラーが発生しました: {:?}", e);
+     response1(
+      conf,
+      self.state.clone(),
+      &source,
+      "set:error",
+      "セット {A} の実行中にエラーが発生しました。",
+      args[0],
+     )
+     .await;
     }
    },
    _ => {
     log::warn!("コマンドまたは何かが違うようです: command = {:?} args = {:?}", command, args);
-    response0(conf, self.state.clone(), "_", "コマンドまたは何かが違うようです。").await;
+    response0(conf, self.state.clone(), &source, "_", "コマンドまたは何かが違うようです。").await;
    },
   }
 
@@ -111,7 +269,7 @@ impl Processor for Command {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   let mut p = Command {
    conf: pc.as_shared(),
    state: state.clone(),
@@ -126,7 +284,7 @@ impl Processor for Command {
    bail!("Command が正常に設定されていません: {:?}", pc);
   }
 
-  Ok(ProcessorKind::Command(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
@@ -164,34 +322,44 @@ impl Processor for Command {
 }
 
 // 0 変数版
-async fn response0(conf: ProcessorConf, state: SharedState, command: &str, default_message: &str) {
+async fn response0(conf: ProcessorConf, state: SharedState, source: &ChannelDatum, command: &str, default_message: &str) {
  let content = conf
   .response_mod
   .iter()
   .find_map(|v| if v[0] == command { Some(v[1].clone()) } else { None })
   .unwrap_or_else(|| default_message.to_string());
- let cd = ChannelDatum::new(conf.channel_to.unwrap(), content).with_flag(ChannelDatum::FLAG_IS_FINAL);
+ let cd = ChannelDatum::new(conf.channel_to.clone().unwrap(), content)
+  .with_flag(ChannelDatum::FLAG_IS_FINAL)
+  .with_source(source, &conf.get_id());
 
  let state = state.read().await;
  state.push_channel_datum(cd).await;
 }
 
 // 1 変数版
-async fn response1(conf: ProcessorConf, state: SharedState, command: &str, default_message: &str, a: &str) {
+async fn response1(conf: ProcessorConf, state: SharedState, source: &ChannelDatum, command: &str, default_message: &str, a: &str) {
  let content = conf
   .response_mod
   .iter()
   .find_map(|v| if v[0] == command { Some(v[1].clone()) } else { None })
   .unwrap_or_else(|| default_message.to_string())
   .replace("{A}", a);
- let cd = ChannelDatum::new(conf.channel_to.unwrap(), content).with_flag(ChannelDatum::FLAG_IS_FINAL);
+ let cd = ChannelDatum::new(conf.channel_to.clone().unwrap(), content)
+  .with_flag(ChannelDatum::FLAG_IS_FINAL)
+  .with_source(source, &conf.get_id());
 
  let state = state.read().await;
  state.push_channel_datum(cd).await;
 }
 
 #[async_recursion::async_recursion]
-async fn activate_command_set(set_name: &str, command_sets: &Vec<CommandSet>, state: SharedState) -> Result<()> {
+async fn activate_command_set(
+ set_name: &str,
+ command_sets: &Vec<CommandSet>,
+ state: SharedState,
+ source: &ChannelDatum,
+ processor_id: &str,
+) -> Result<()> {
  // find
  let command = command_sets
   .iter()
@@ -199,20 +367,22 @@ async fn activate_command_set(set_name: &str, command_sets: &Vec<CommandSet>, st
   .ok_or_else(|| anyhow::anyhow!("セット名 {:?} が見つかりませんでした。", set_name))?;
 
  for pre in &command.pre {
-  if let Err(e) = activate_command_set(&pre, command_sets, state.clone()).await {
+  if let Err(e) = activate_command_set(&pre, command_sets, state.clone(), source, processor_id).await {
    log::error!("pre 処理でエラーが発生しました: {:?}", e);
   }
  }
 
  for cc in &command.channel_contents {
   // log::warn!("channel = {:?} に content = {:?} を送信します。", cc.channel, cc.content)
-  let cd = ChannelDatum::new(cc.channel.clone(), cc.content.clone()).with_flag(ChannelDatum::FLAG_IS_FINAL);
+  let cd = ChannelDatum::new(cc.channel.clone(), cc.content.clone())
+   .with_flag(ChannelDatum::FLAG_IS_FINAL)
+   .with_source(source, processor_id);
   let state = state.read().await;
   state.push_channel_datum(cd).await;
  }
 
  for post in &command.post {
-  if let Err(e) = activate_command_set(&post, command_sets, state.clone()).await {
+  if let Err(e) = activate_command_set(&post, command_sets, state.clone(), source, processor_id).await {
    log::error!("post 処理でエラーが発生しました: {:?}", e);
   }
  }
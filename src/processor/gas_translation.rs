@@ -1,5 +1,5 @@
 use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
+use crate::{ChannelDatum, ProcessorConf, SharedChannelData, SharedProcessorConf, SharedState};
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 
@@ -29,10 +29,11 @@ impl Processor for GasTranslation {
   let url_base = self.url_base.clone();
   let channel_to = conf.channel_to.as_ref().unwrap().clone();
   let translate_to = conf.translate_to.as_ref().unwrap().clone();
+  let processor_id = conf.get_id();
 
   tokio::spawn(async move {
    // 翻訳元を取得
-   let (source, has_final) = {
+   let (source_datum, source, has_final) = {
     let channel_data = channel_data.read().await;
     match channel_data.iter().rev().find(|cd| cd.get_id() == id) {
      Some(source) => {
@@ -48,7 +49,7 @@ impl Processor for GasTranslation {
        return Ok(());
       }
 
-      (source.content.clone(), source.has_flag(ChannelDatum::FLAG_IS_FINAL))
+      (source.clone(), source.content.clone(), source.has_flag(ChannelDatum::FLAG_IS_FINAL))
      },
      None => bail!("指定された id の ChannelDatum が見つかりませんでした: {}", id),
     }
@@ -83,14 +84,10 @@ impl Processor for GasTranslation {
    log::debug!("output_content = {}", output_content);
 
    // 翻訳結果を書き込み
-   let mut output_channel_datum = ChannelDatum::new(channel_to, output_content).with_flag(&format!(
-    "{}({}:{},{}/{})",
-    Self::FEATURE,
-    "gas-translation",
-    id,
-    translate_from,
-    translate_to
-   ));
+   let mut output_channel_datum = ChannelDatum::new(channel_to, output_content)
+    .with_source(&source_datum, &processor_id)
+    .with_lang(&translate_to)
+    .with_source_lang(&translate_from);
    if has_final {
     output_channel_datum = output_channel_datum.with_flag(ChannelDatum::FLAG_IS_FINAL);
    }
@@ -111,7 +108,7 @@ impl Processor for GasTranslation {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   let url_base = {
    let script_id = match crate::utility::load_from_env_or_conf(ENV_GAS_TRANSLATION_SCRIPT_ID, &pc.script_id) {
     Some(v) => v,
@@ -142,7 +139,7 @@ impl Processor for GasTranslation {
    bail!("GasTranslation が正常に設定されていません: {:?}", pc);
   }
 
-  Ok(ProcessorKind::GasTranslation(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
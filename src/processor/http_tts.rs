@@ -0,0 +1,201 @@
+use super::{TtsAudio, TtsEngine, TtsProcessor};
+use crate::ProcessorConf;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use regex::Regex;
+use reqwest::{header::CONTENT_TYPE, Method};
+use std::sync::LazyLock;
+
+/// 設定ファイルで組み立てた HTTP リクエストで任意の音声合成サーバーを使う Processor です。
+/// Style-Bert-VITS2 や GPT-SoVITS 、 Piper の HTTP サーバーなど、新しい音声合成サーバーも設定だけで使えます。
+pub type HttpTts = TtsProcessor<HttpTtsEngine>;
+
+const DEFAULT_HTTP_METHOD: &str = "POST";
+
+/// テンプレートの {名前}
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{([a-z_]+)\}").unwrap());
+
+#[derive(Debug, Clone)]
+pub struct HttpTtsEngine {
+ method: Method,
+ client: reqwest::Client,
+}
+
+/// テンプレートの値の埋め込み方
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+ /// そのまま埋め込む
+ None,
+ /// URL エンコードして埋め込む
+ Url,
+ /// JSON の文字列の中身としてエスケープして埋め込む
+ Json,
+ /// HTTP ヘッダーの値に使えない ASCII 以外の文字と制御文字だけ URL エンコードして埋め込む
+ Header,
+}
+
+#[async_trait]
+impl TtsEngine for HttpTtsEngine {
+ const FEATURE: &'static str = "http-tts";
+ const NAME: &'static str = "HttpTts";
+
+ async fn new(pc: &ProcessorConf) -> Result<Self> {
+  if pc.api_url.is_none() {
+   bail!("api_url が設定されていません。");
+  }
+
+  let method = pc.http_method.as_deref().unwrap_or(DEFAULT_HTTP_METHOD).to_uppercase();
+  let method = Method::from_bytes(method.as_bytes()).with_context(|| format!("http_method が不正です: {:?}", pc.http_method))?;
+
+  Ok(HttpTtsEngine {
+   method,
+   client: reqwest::Client::new(),
+  })
+ }
+
+ async fn is_established(&mut self, conf: &ProcessorConf) -> bool {
+  if let Some(body) = conf.http_body.as_ref() {
+   // 仮の値を埋め込んで JSON として正しいか確認
+   let body = fill_template(body, &template_params("", conf), Escape::Json);
+   if let Err(e) = serde_json::from_str::<serde_json::Value>(&body) {
+    log::error!("http_body に値を埋め込んだ結果が JSON として不正です: {:?} {}", e, body);
+    return false;
+   }
+  }
+
+  log::info!(
+   "HttpTts は正常に設定されています: channel: {:?} method: {} api_url: {:?} query: {:?} headers: {:?} body: {:?} response_audio_json_pointer: {:?}",
+   conf.channel_from,
+   self.method,
+   conf.api_url,
+   conf.http_query,
+   conf.http_headers.keys().collect::<Vec<_>>(),
+   conf.http_body,
+   conf.http_response_audio_json_pointer,
+  );
+  true
+ }
+
+ async fn synthesize(&self, text: &str, pc: &ProcessorConf) -> Result<TtsAudio> {
+  let params = template_params(text, pc);
+
+  let url = fill_template(pc.api_url.as_ref().unwrap(), &params, Escape::Url);
+  let mut request = self.client.request(self.method.clone(), &url);
+
+  let query = pc
+   .http_query
+   .iter()
+   .map(|(k, v)| (k.clone(), fill_template(v, &params, Escape::None)))
+   .collect::<Vec<_>>();
+  if !query.is_empty() {
+   request = request.query(&query);
+  }
+
+  for (k, v) in pc.http_headers.iter() {
+   request = request.header(k, fill_template(v, &params, Escape::Header));
+  }
+
+  if let Some(body) = pc.http_body.as_ref() {
+   if !pc.http_headers.keys().any(|k| k.eq_ignore_ascii_case(CONTENT_TYPE.as_str())) {
+    request = request.header(CONTENT_TYPE, "application/json");
+   }
+   request = request.body(fill_template(body, &params, Escape::Json));
+  }
+
+  log::trace!("HttpTts にリクエストを送信します: {} {}", self.method, url);
+  let response = request.send().await?.error_for_status()?;
+  let payload = response.bytes().await?;
+
+  let audio = match pc.http_response_audio_json_pointer.as_ref() {
+   // JSON の中の base64 の音声データ
+   Some(pointer) => {
+    let json = serde_json::from_slice::<serde_json::Value>(&payload).with_context(|| "応答を JSON として解釈できませんでした。")?;
+    let base64 = json
+     .pointer(pointer)
+     .and_then(|v| v.as_str())
+     .with_context(|| format!("応答の JSON に {} の文字列がありませんでした。", pointer))?;
+    // data:audio/wav;base64,... の形式の場合は , より後ろがデータ
+    let base64 = match base64.starts_with("data:") {
+     true => base64.split_once(',').map(|(_, data)| data).unwrap_or_default(),
+     false => base64,
+    };
+    general_purpose::STANDARD
+     .decode(base64.trim())
+     .with_context(|| "応答の音声データの base64 のデコードに失敗しました。")?
+   },
+   // 応答そのものが WAV, MP3, OGG などの音声データ
+   None => payload.to_vec(),
+  };
+
+  Ok(TtsAudio::Data(audio))
+ }
+
+ fn voice_key(&self, pc: &ProcessorConf) -> Option<String> {
+  // text 以外のテンプレートの値とリクエストの組み立て方が同じなら同じ音声とみなす
+  let params = template_params("", pc)
+   .into_iter()
+   .filter(|(name, _)| *name != "text")
+   .collect::<Vec<_>>();
+  let voice = serde_json::json!({
+   "method": self.method.as_str(),
+   "api_url": pc.api_url,
+   "query": pc.http_query,
+   "headers": pc.http_headers,
+   "body": pc.http_body,
+   "response_audio_json_pointer": pc.http_response_audio_json_pointer,
+   "params": params,
+  });
+  Some(voice.to_string())
+ }
+}
+
+/// テンプレートに埋め込める {名前} と値の組を作ります。設定されていない値は空文字列になります。
+fn template_params(text: &str, pc: &ProcessorConf) -> Vec<(&'static str, String)> {
+ fn opt<T: ToString>(v: &Option<T>) -> String {
+  v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+ }
+ vec![
+  ("text", text.to_string()),
+  ("lang", opt(&pc.lang)),
+  ("api_key", opt(&pc.api_key)),
+  ("speaker_uuid", opt(&pc.speaker_uuid)),
+  ("style_id", opt(&pc.style_id)),
+  ("voice_id", opt(&pc.voice_id)),
+  ("voice_name", opt(&pc.voice_name)),
+  ("speed_scale", opt(&pc.speed_scale)),
+  ("pitch_scale", opt(&pc.pitch_scale)),
+  ("intonation_scale", opt(&pc.intonation_scale)),
+  ("volume_scale", opt(&pc.volume_scale)),
+  ("output_sampling_rate", opt(&pc.output_sampling_rate)),
+ ]
+}
+
+/// template の {名前} を params の値で置換します。埋め込んだ値の中の {名前} は置換しません。
+fn fill_template(template: &str, params: &[(&'static str, String)], escape: Escape) -> String {
+ PLACEHOLDER
+  .replace_all(template, |captures: &regex::Captures| {
+   let value = match params.iter().find(|(name, _)| *name == &captures[1]) {
+    Some((_, value)) => value,
+    // 知らない名前はそのまま残す
+    None => return captures[0].to_string(),
+   };
+   match escape {
+    Escape::None => value.clone(),
+    Escape::Url => urlencoding::encode(value).to_string(),
+    Escape::Json => {
+     let quoted = serde_json::Value::String(value.clone()).to_string();
+     quoted[1..quoted.len() - 1].to_string()
+    },
+    // api_key などの + / = はそのままにしたいので urlencoding::encode は使わない
+    Escape::Header => value
+     .chars()
+     .map(|c| match c.is_ascii() && !c.is_ascii_control() {
+      true => c.to_string(),
+      false => urlencoding::encode(c.encode_utf8(&mut [0; 4])).to_string(),
+     })
+     .collect(),
+   }
+  })
+  .to_string()
+}
@@ -2,97 +2,102 @@ mod bouyomichan;
 mod coeiroink;
 mod command;
 mod gas_translation;
+mod http_tts;
 mod modify;
 mod ocr;
 mod openai_chat;
 mod os_tts;
+mod registry;
 mod screenshot;
+mod tts_engine;
+mod voice_markup;
+mod voicevox;
 
-pub use bouyomichan::Bouyomichan;
-pub use coeiroink::CoeiroInk;
+pub use bouyomichan::{Bouyomichan, BouyomichanEngine};
+pub use coeiroink::{CoeiroInk, CoeiroInkEngine, DEFAULT_COEIROINK_API_URL};
 pub use command::Command;
 pub use gas_translation::GasTranslation;
+pub use http_tts::{HttpTts, HttpTtsEngine};
 pub use modify::Modify;
 pub use ocr::Ocr;
 pub use openai_chat::OpenAiChat;
-pub use os_tts::OsTts;
+pub use os_tts::{OsTts, OsTtsEngine};
+pub use registry::{ProcessorFactory, ProcessorRegistry};
 pub use screenshot::Screenshot;
+pub use tts_engine::{SpeakersCache, TtsAudio, TtsEngine, TtsProcessor};
+pub use voice_markup::VoiceMarkup;
+pub use voicevox::{Voicevox, VoicevoxEngine, VoicevoxSpeaker, VoicevoxSpeakerStyle, DEFAULT_VOICEVOX_API_URL};
 
-use crate::{ProcessorConf, SharedProcessorConf, SharedState};
+use crate::{Arc, ProcessorConf, SharedProcessorConf, SharedState};
 use anyhow::Result;
 use async_trait::async_trait;
 
+/// Processor の実装用のトレイトです。
+/// 実装した Processor は ProcessorRegistry::register で FEATURE をキーとして登録すると設定ファイルの feature から使えるようになります。
 #[async_trait]
-pub trait Processor {
+pub trait Processor: Send + Sync + std::fmt::Debug {
  /// Processor の種類を表す他の Processor と重複しない一意の文字列。
  const FEATURE: &'static str;
  /// 処理本体
  async fn process(&self, id: u64) -> Result<CompletedAnd>;
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind>;
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self>;
  async fn is_established(&mut self) -> bool;
  async fn is_channel_from(&self, channel_from: &str) -> bool;
  fn conf(&self) -> SharedProcessorConf;
+ /// text を読み上げずに音声合成して音声のキャッシュへ保存し、新たに保存した数を返します。音声合成しない Processor では何もしません。
+ async fn prewarm_audio_cache(&self, _text: &str) -> Result<usize> {
+  Ok(0)
+ }
+}
+
+/// State が保持する型消去済みの Processor です。 Processor を実装した型には自動的に実装されます。
+#[async_trait]
+pub trait DynProcessor: Send + Sync + std::fmt::Debug {
+ fn feature(&self) -> &'static str;
+ async fn process(&self, id: u64) -> Result<CompletedAnd>;
+ async fn is_channel_from(&self, channel_from: &str) -> bool;
+ fn conf(&self) -> SharedProcessorConf;
+ async fn prewarm_audio_cache(&self, text: &str) -> Result<usize>;
+
+ /// 来歴やログで Processor を見分けるための ID
+ async fn get_id(&self) -> String {
+  self.conf().read().await.get_id()
+ }
+
+ /// /enable, /disable で切り替えられる実行時の有効/無効
+ async fn is_enabled(&self) -> bool {
+  self.conf().read().await.is_enabled
+ }
 }
 
-// Note: 追加/削除する場合は
-//  1. ProcessorKind
-//  2. ProcessorKind::is_channel_from
-//  3. ProcessorKind::process
-//  4. State::init_processors
-// も変更すること
-#[derive(Debug, Clone)]
-pub enum ProcessorKind {
- // common
- Command(Command),
- Modify(Modify),
- Screenshot(Screenshot),
- Ocr(Ocr),
+#[async_trait]
+impl<P: Processor + 'static> DynProcessor for P {
+ fn feature(&self) -> &'static str {
+  P::FEATURE
+ }
+
+ async fn process(&self, id: u64) -> Result<CompletedAnd> {
+  log::debug!("DynProcessor::process() が呼び出されました。 feature = {:?}", P::FEATURE);
+  Processor::process(self, id).await
+ }
 
- // ai
- OpenAiChat(OpenAiChat),
+ async fn is_channel_from(&self, channel_from: &str) -> bool {
+  Processor::is_channel_from(self, channel_from).await
+ }
 
- // translation
- GasTranslation(GasTranslation),
+ fn conf(&self) -> SharedProcessorConf {
+  Processor::conf(self)
+ }
 
- // tts
- OsTts(OsTts),
- CoeiroInk(CoeiroInk),
- Bouyomichan(Bouyomichan),
+ async fn prewarm_audio_cache(&self, text: &str) -> Result<usize> {
+  Processor::prewarm_audio_cache(self, text).await
+ }
 }
 
+pub type SharedProcessor = Arc<dyn DynProcessor>;
+
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum CompletedAnd {
  Next,
  Break,
 }
-
-impl ProcessorKind {
- pub async fn is_channel_from(&self, channel_from: &str) -> bool {
-  match self {
-   Self::Command(p) => p.is_channel_from(channel_from),
-   Self::Modify(p) => p.is_channel_from(channel_from),
-   Self::Screenshot(p) => p.is_channel_from(channel_from),
-   Self::Ocr(p) => p.is_channel_from(channel_from),
-   Self::OpenAiChat(p) => p.is_channel_from(channel_from),
-   Self::GasTranslation(p) => p.is_channel_from(channel_from),
-   Self::Bouyomichan(p) => p.is_channel_from(channel_from),
-   Self::CoeiroInk(p) => p.is_channel_from(channel_from),
-   Self::OsTts(p) => p.is_channel_from(channel_from),
-  }.await
- }
-
- pub async fn process(&self, id: u64) -> Result<CompletedAnd> {
-  log::debug!("ProcessorKind::process() が呼び出されました。");
-  match self {
-   Self::Command(p) => p.process(id).await,
-   Self::Modify(p) => p.process(id).await,
-   Self::Screenshot(p) => p.process(id).await,
-   Self::Ocr(p) => p.process(id).await,
-   Self::OpenAiChat(p) => p.process(id).await,
-   Self::GasTranslation(p) => p.process(id).await,
-   Self::CoeiroInk(p) => p.process(id).await,
-   Self::Bouyomichan(p) => p.process(id).await,
-   Self::OsTts(p) => p.process(id).await,
-  }
- }
-}
@@ -1,5 +1,5 @@
 use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
+use crate::{ChannelDatum, ChannelEventBus, ProcessorConf, SharedChannelData, SharedProcessorConf, SharedState};
 use alkana_rs::ALKANA;
 use anyhow::{bail, Result};
 use async_trait::async_trait;
@@ -14,6 +14,7 @@ pub struct Modify {
  channel_to: String,
 
  channel_data: SharedChannelData,
+ channel_event_bus: ChannelEventBus,
  // to <- from
  dictionary: Vec<(String, String)>,
  // to_replacer <- from_matcher
@@ -76,12 +77,15 @@ impl Processor for Modify {
    // self.modify が true の場合は、変換後の文字列を元 &mut ChannelDatum の文字列に上書き
    log::debug!("変換後の文字列を元の文字列に上書きします。");
    channel_data[index].content = content;
+   channel_data[index].update_revision(&conf.get_id());
+   self.channel_event_bus.publish(&channel_data[index]);
   } else {
-   let channel_data_clone = channel_data[index].clone();
+   let source = channel_data[index].clone();
    // unlock
    drop(channel_data);
    let channel_to = self.channel_to.clone();
    let state = self.state.clone();
+   let processor_id = conf.get_id();
    log::debug!(
     "変換後の文字列を新しい ChannelDatum として追加します。 {:?} -> {:?}",
     self.channel_from,
@@ -93,9 +97,10 @@ impl Processor for Modify {
      .read()
      .await
      .push_channel_datum(
-      ChannelDatum::move_from(channel_data_clone)
+      ChannelDatum::move_from(source.clone())
        .with_channel(channel_to)
-       .with_content(content),
+       .with_content(content)
+       .with_source(&source, &processor_id),
      )
      .await;
    });
@@ -108,13 +113,14 @@ impl Processor for Modify {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   let mut p = Modify {
    conf: pc.as_shared(),
    state: state.clone(),
    channel_from: "".to_string(),
    channel_to: "".to_string(),
    channel_data: state.read().await.channel_data.clone(),
+   channel_event_bus: state.read().await.channel_event_bus.clone(),
    dictionary: vec![],
    regexes: vec![],
   };
@@ -221,7 +227,7 @@ impl Processor for Modify {
    }
   }
 
-  Ok(ProcessorKind::Modify(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
@@ -3,7 +3,7 @@ mod local;
 mod web;
 
 use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
+use crate::{ChannelDatum, ProcessorConf, SharedChannelData, SharedProcessorConf, SharedState};
 use anyhow::{bail, ensure, Result};
 use async_tempfile::TempFile;
 use async_trait::async_trait;
@@ -37,6 +37,7 @@ impl Processor for Ocr {
   let lang = self.lang.to_lowercase();
   let lines = conf.lines.unwrap_or_default();
   let auto_delete_processed_file = conf.auto_delete_processed_file.unwrap_or_default();
+  let processor_id = conf.get_id();
 
   tokio::spawn(async move {
    // conf と contents に応じて画像ファイルをローカルに生成ないしパスを保持
@@ -45,6 +46,9 @@ impl Processor for Ocr {
    // 処理対象群を格納
    let mut targets = vec![];
 
+   // 出力に来歴を引き継ぐため入力を取得
+   let source = channel_data.read().await.iter().rev().find(|cd| cd.get_id() == id).cloned();
+
    // conf.load_from から画像ファイルを取得して処理対象に追加
    let load_from_len = conf.load_from.len();
    for (index, url_or_path_pattern) in conf.load_from.iter().enumerate() {
@@ -188,11 +192,13 @@ impl Processor for Ocr {
 
     // lock state -> push datum
     {
+     let mut output_datum = ChannelDatum::new(channel_to.clone(), output).with_flag(ChannelDatum::FLAG_IS_FINAL);
+     if let Some(source) = source.as_ref() {
+      output_datum = output_datum.with_source(source, &processor_id);
+     }
      let state = state.read().await;
      // push datum
-     state
-      .push_channel_datum(ChannelDatum::new(channel_to.clone(), output).with_flag(ChannelDatum::FLAG_IS_FINAL))
-      .await;
+     state.push_channel_datum(output_datum).await;
     }
 
     // auto remove file
@@ -219,7 +225,7 @@ impl Processor for Ocr {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   // TODO: Windows 以外のサポート
   #[cfg(not(target_os = "windows"))]
   {
@@ -243,7 +249,7 @@ impl Processor for Ocr {
    bail!("ocr が正常に設定されていません: {:?}", pc);
   }
 
-  Ok(ProcessorKind::Ocr(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
@@ -1,7 +1,7 @@
 mod fine_tuning;
 
 use super::{CompletedAnd, Processor};
-use crate::{Arc, ChannelDatum, Mutex, ProcessorConf, ProcessorKind, SharedChannelData, SharedProcessorConf, SharedState};
+use crate::{Arc, ChannelDatum, Mutex, ProcessorConf, SharedChannelData, SharedProcessorConf, SharedState};
 
 use anyhow::{bail, Context, Result};
 use async_openai::{
@@ -62,10 +62,11 @@ impl Processor for OpenAiChat {
    .cloned()
    .unwrap_or_else(|| DEFAULT_REMOVE_CHARS.to_string());
   let fine_tuning = conf.fine_tuning.as_ref().cloned();
+  let processor_id = conf.get_id();
 
   tokio::spawn(async move {
    // 入力を取得
-   let reversed_sources = {
+   let (source, reversed_sources) = {
     let channel_data = channel_data.read().await;
     // channel_data から id の ChannelDatum の位置を取得
     let index = channel_data.iter().rev().position(|cd| cd.get_id() == id);
@@ -76,13 +77,14 @@ impl Processor for OpenAiChat {
     let index = index.unwrap();
 
     // index の ChannelDatum が .has_flag で IS_FINAL フラグか確認
-    if !channel_data.iter().rev().nth(index).unwrap().has_flag(ChannelDatum::FLAG_IS_FINAL) {
+    let source = channel_data.iter().rev().nth(index).unwrap().clone();
+    if !source.has_flag(ChannelDatum::FLAG_IS_FINAL) {
      log::trace!("未確定の入力なので、処理をスキップします。");
      return Ok(());
     }
 
     // index の ChannelDatum から memory_capacity 件の IS_FINAL フラグが有効かつ channel が channel_from または channel_to の ChannelDatum を取得
-    channel_data
+    let reversed_sources = channel_data
      .iter()
      .rev()
      .skip(index)
@@ -90,7 +92,8 @@ impl Processor for OpenAiChat {
      .filter(|cd| cd.channel == channel_from || cd.channel == channel_to)
      .take(memory_capacity)
      .cloned()
-     .collect::<Vec<_>>()
+     .collect::<Vec<_>>();
+    (source, reversed_sources)
    };
 
    // トリガーになった id の user 発言を保持(fine-tuning 用)
@@ -357,7 +360,7 @@ impl Processor for OpenAiChat {
 
    let datum = ChannelDatum::new(channel_to, content)
     .with_flag(ChannelDatum::FLAG_IS_FINAL)
-    .with_flag(&format!("{}({}:{})", Self::FEATURE, channel_from, id));
+    .with_source(&source, &processor_id);
 
    {
     let state = state.read().await;
@@ -376,7 +379,7 @@ impl Processor for OpenAiChat {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   let mut p = OpenAiChat {
    conf: pc.as_shared(),
    state: state.clone(),
@@ -399,7 +402,7 @@ impl Processor for OpenAiChat {
    p.ignore_regex = conf.ignore_regex_pattern.as_ref().map(|s| Regex::new(s).unwrap());
   }
 
-  Ok(ProcessorKind::OpenAiChat(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
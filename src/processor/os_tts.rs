@@ -1,115 +1,105 @@
-use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedAudioSink, SharedChannelData, SharedProcessorConf, SharedState};
+use super::{TtsAudio, TtsEngine, TtsProcessor, VoiceMarkup};
+use crate::{ProcessorConf, SharedAudioSink};
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 
+/// OS の音声合成エンジンで読み上げる Processor です。
+pub type OsTts = TtsProcessor<OsTtsEngine>;
+
+/// Linux で音声データを作るのに使うコマンド。先に見つかったものを使います。
+const ESPEAK_COMMANDS: [&str; 2] = ["espeak-ng", "espeak"];
+
 #[derive(Clone)]
-pub struct OsTts {
- conf: SharedProcessorConf,
- channel_data: SharedChannelData,
+pub struct OsTtsEngine {
  tts: tts::Tts,
- audio_sink: SharedAudioSink,
+ /// Linux で見つかった espeak-ng または espeak 。見つからない場合は tts が直接再生します。
+ espeak: Option<String>,
+ /// is_established で選んだ声の言語から決めた espeak-ng の声。 None なら espeak-ng の標準の声
+ espeak_voice: Option<String>,
 }
 
 // Debug を手動実装
-impl std::fmt::Debug for OsTts {
+impl std::fmt::Debug for OsTtsEngine {
  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-  f.debug_struct("OsTts")
-   .field("conf", &self.conf)
-   .field("channel_data", &self.channel_data)
+  f.debug_struct("OsTtsEngine")
+   .field("espeak", &self.espeak)
+   .field("espeak_voice", &self.espeak_voice)
    .finish()
  }
 }
 
 #[async_trait]
-impl Processor for OsTts {
+impl TtsEngine for OsTtsEngine {
  const FEATURE: &'static str = "os-tts";
+ const NAME: &'static str = "OsTts";
 
- async fn process(&self, id: u64) -> Result<CompletedAnd> {
-  log::debug!("OsTts::process() が呼び出されました。");
-
-  let channel_data = self.channel_data.clone();
-  let mut tts = self.tts.clone();
-  let audio_sink = self.audio_sink.clone();
-
-  tokio::spawn(async move {
-   // 入力を取得
-   let source = {
-    let channel_data = channel_data.read().await;
-    match channel_data.iter().rev().find(|cd| cd.get_id() == id) {
-     Some(source) if source.has_flag(ChannelDatum::FLAG_IS_FINAL) => source.content.clone(),
-     Some(_) => {
-      log::trace!("未確定の入力なので、処理をスキップします。");
-      return Ok(());
-     },
-     None => bail!("指定された id の ChannelDatum が見つかりませんでした: {}", id),
-    }
-   };
-
-   log::debug!("OsTts に音声合成をリクエストします。");
-   #[cfg(not(target_os = "windows"))]
-   if let Some(e) = tts.speak(source, false).err() {
-    log::error!("音声合成に失敗しました: {:?}", e);
-    return Ok(());
-   }
-   #[cfg(target_os = "windows")]
-   match tts.synthesize(source) {
-    Ok(wav) => {
-     let cursor = std::io::Cursor::new(wav);
-     let source = rodio::Decoder::new(cursor).unwrap();
-     audio_sink.lock().await.0.append(source)
-    },
-    Err(e) => {
-     log::error!("音声合成に失敗しました: {:?}", e);
-     return Ok(());
-    },
-   }
-
-   // tts の処理が終わるまで待機
-   #[cfg(not(target_os = "windows"))]
-   while tts.is_speaking().unwrap() {
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-   }
-
-   Ok(())
-  });
+ async fn new(_pc: &ProcessorConf) -> Result<Self> {
+  let espeak = match cfg!(target_os = "linux") {
+   true => find_espeak().await,
+   false => None,
+  };
+  if cfg!(target_os = "linux") && espeak.is_none() {
+   log::warn!(
+    "espeak-ng または espeak が見つからないため speech-dispatcher が直接再生します。 audio_file_store_path, channel_to, lip_sync_channel などを使うには espeak-ng をインストールしてください。"
+   );
+  }
+  Ok(OsTtsEngine {
+   tts: tts::Tts::default()?,
+   espeak,
+   espeak_voice: None,
+  })
+ }
 
-  log::trace!("OsTts::process() は非同期処理を開始しました。");
+ async fn synthesize(&self, text: &str, pc: &ProcessorConf) -> Result<TtsAudio> {
+  // Linux では espeak-ng で WAV を作り Windows と同じく VAC で再生
+  if let Some(espeak) = self.espeak.as_ref() {
+   // speech-dispatcher の直接再生へ切り替えると returns_audio と食い違い、並列に合成した文が重なって再生されるためエラーにする
+   return Ok(TtsAudio::Data(
+    espeak_synthesize(espeak, self.espeak_voice.as_deref(), text, pc).await?,
+   ));
+  }
 
-  Ok(CompletedAnd::Next)
+  let mut tts = self.tts.clone();
+  // 音声データを取得できない場合は tts が直接再生
+  #[cfg(not(target_os = "windows"))]
+  let audio = {
+   tts.speak(text, false)?;
+   TtsAudio::Speaking
+  };
+  #[cfg(target_os = "windows")]
+  let audio = TtsAudio::Data(tts.synthesize(text.to_string())?.to_vec());
+  Ok(audio)
  }
 
- fn conf(&self) -> SharedProcessorConf {
-  self.conf.clone()
+ fn returns_audio(&self) -> bool {
+  cfg!(target_os = "windows") || self.espeak.is_some()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
-  let mut p = OsTts {
-   conf: pc.as_shared(),
-   channel_data: state.read().await.channel_data.clone(),
-   tts: tts::Tts::default()?,
-   audio_sink: state.read().await.audio_sink.clone(),
-  };
+ async fn apply_markup(&self, _pc: &mut ProcessorConf, _markup: &VoiceMarkup) -> Result<()> {
+  // 声や速度は is_established で tts に設定したものが全ての読み上げで共有されるため
+  bail!("OsTts は読み上げのタグによる声や速度の切り替えに対応していません。")
+ }
 
-  if !p.is_established().await {
-   bail!("OsTts が正常に設定されていません: {:?}", pc);
+ fn voice_key(&self, pc: &ProcessorConf) -> Option<String> {
+  // 音声データを取得できるのは Windows と espeak-ng のある Linux のみ
+  if !self.returns_audio() {
+   return None;
   }
-
-  Ok(ProcessorKind::OsTts(p))
+  Some(format!(
+   "{:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+   self.espeak, self.espeak_voice, pc.voice_id, pc.voice_name, pc.tts_rate, pc.tts_pitch, pc.tts_volume
+  ))
  }
 
- async fn is_channel_from(&self, channel_from: &str) -> bool {
-  let conf = self.conf.read().await;
-  conf.channel_from.as_ref().unwrap() == channel_from
+ async fn wait_until_spoken(&self) {
+  // tts の処理が終わるまで待機
+  #[cfg(not(target_os = "windows"))]
+  while self.tts.is_speaking().unwrap_or(false) {
+   tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+  }
  }
 
- async fn is_established(&mut self) -> bool {
-  let conf = self.conf.read().await;
-
-  if conf.channel_from.is_none() {
-   log::error!("channel_from が設定されていません。");
-   return false;
-  }
+ async fn is_established(&mut self, conf: &ProcessorConf) -> bool {
   let voices = if let Ok(voice) = self.tts.voices() {
    voice
   } else {
@@ -118,6 +108,8 @@ impl Processor for OsTts {
   };
 
   let mut is_voice_set = false;
+  // speech-dispatcher では tts.voice() で設定した声を取得できないため、見つけた声の言語を控えておく
+  let mut voice_language = None;
 
   if let Some(ref id) = conf.voice_id {
    if let Some(voice) = voices.iter().find(|v| v.id().eq(id)) {
@@ -126,6 +118,7 @@ impl Processor for OsTts {
      return false;
     }
     is_voice_set = true;
+    voice_language = Some(voice.language());
    }
   }
 
@@ -138,6 +131,7 @@ impl Processor for OsTts {
       return false;
      }
      is_voice_set = true;
+     voice_language = Some(voice.language());
     }
    }
   }
@@ -146,6 +140,19 @@ impl Processor for OsTts {
    log::warn!("voice_name も voice_id も設定されていないため OS のデフォルトの音声を使用します。")
   }
 
+  // voice_id, voice_name は speech-dispatcher の声なので espeak-ng には選んだ声の言語 (ja, en など) を渡す
+  if let Some(espeak) = self.espeak.as_ref() {
+   self.espeak_voice = voice_language.map(|language| language.primary_language().to_lowercase());
+   if let Some(espeak_voice) = self.espeak_voice.as_ref() {
+    log::warn!(
+     "{} で音声合成するため voice_id, voice_name の声そのものではなく、その言語 {:?} の {} の声で読み上げます。",
+     espeak,
+     espeak_voice,
+     espeak
+    );
+   }
+  }
+
   if let Some(mut rate) = conf.tts_rate {
    let min = self.tts.min_rate();
    let max = self.tts.max_rate();
@@ -203,7 +210,8 @@ impl Processor for OsTts {
   log::info!(
    "OsTts は正常に設定されています: channel: {:?} voice: {:?} rate: {:?} pitch: {:?} volume: {:?}",
    conf.channel_from,
-   self.tts.voice().unwrap().unwrap(),
+   // speech-dispatcher は設定した声を取得できないので None になる
+   self.tts.voice().ok().flatten(),
    self.tts.get_rate(),
    self.tts.get_pitch(),
    self.tts.get_volume(),
@@ -212,6 +220,55 @@ impl Processor for OsTts {
  }
 }
 
+/// ESPEAK_COMMANDS から実行できるものを探します。
+async fn find_espeak() -> Option<String> {
+ for command in ESPEAK_COMMANDS {
+  let output = tokio::process::Command::new(command).arg("--version").output().await;
+  if matches!(output, Ok(output) if output.status.success()) {
+   log::debug!("OS-TTS の音声合成に {} を使います。", command);
+   return Some(command.to_string());
+  }
+ }
+ None
+}
+
+/// espeak-ng (espeak) で text を WAV にします。 voice は espeak-ng の声の名前です。
+/// tts_rate, tts_pitch, tts_volume は speech-dispatcher と同じ -100 から 100 (volume は 100 が標準) の値を espeak-ng の値へ変換します。
+async fn espeak_synthesize(espeak: &str, voice: Option<&str>, text: &str, pc: &ProcessorConf) -> Result<Vec<u8>> {
+ let mut command = tokio::process::Command::new(espeak);
+ command.arg("--stdout");
+ if let Some(voice) = voice {
+  command.arg("-v").arg(voice);
+ }
+ if let Some(rate) = pc.tts_rate {
+  // 0 が標準の 175 語/分、 -100 で 80 、 100 で 450
+  let rate = rate.clamp(-100.0, 100.0);
+  let speed = match rate < 0.0 {
+   true => 175.0 + rate * 0.95,
+   false => 175.0 + rate * 2.75,
+  };
+  command.arg("-s").arg((speed.round() as i32).to_string());
+ }
+ if let Some(pitch) = pc.tts_pitch {
+  // 0 が標準の 50 、範囲は 0 から 99
+  let pitch = ((pitch.clamp(-100.0, 100.0) + 100.0) / 2.0).min(99.0);
+  command.arg("-p").arg((pitch.round() as i32).to_string());
+ }
+ if let Some(volume) = pc.tts_volume {
+  // 100 が標準の 100 、 -100 で 0
+  let amplitude = (volume.clamp(-100.0, 100.0) + 100.0) / 2.0;
+  command.arg("-a").arg((amplitude.round() as i32).to_string());
+ }
+ // - で始まる内容がオプションとみなされないよう -- の後ろに置く
+ command.arg("--").arg(text);
+
+ let output = command.output().await?;
+ if !output.status.success() {
+  bail!("{} の実行に失敗しました: {}", espeak, String::from_utf8_lossy(&output.stderr));
+ }
+ Ok(output.stdout)
+}
+
 impl OsTts {
  /// OS-TTS のテストを実行します。コマンドライン引数からの実行を想定しています。
  pub async fn test(audio_sink: SharedAudioSink) -> Result<()> {
@@ -0,0 +1,85 @@
+use super::{
+ Bouyomichan, CoeiroInk, Command, GasTranslation, HttpTts, Modify, Ocr, OpenAiChat, OsTts, Processor, Screenshot, SharedProcessor, Voicevox,
+};
+use crate::{Arc, ProcessorConf, SharedState};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+
+/// ProcessorConf と State から Processor を生成するファクトリーです。
+pub type ProcessorFactory = Arc<dyn Fn(ProcessorConf, SharedState) -> BoxFuture<'static, Result<SharedProcessor>> + Send + Sync>;
+
+/// feature (Processor::FEATURE) をキーとして Processor のファクトリーを保持します。
+/// ライブラリーとして使う場合は独自の Processor を register してから virtual_avatar_connect::run_with_registry に渡せます。
+#[derive(Clone, Default)]
+pub struct ProcessorRegistry {
+ factories: BTreeMap<String, ProcessorFactory>,
+}
+
+impl std::fmt::Debug for ProcessorRegistry {
+ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+  f.debug_struct("ProcessorRegistry").field("features", &self.features()).finish()
+ }
+}
+
+impl ProcessorRegistry {
+ /// 何も登録されていない ProcessorRegistry を生成します。
+ pub fn new() -> Self {
+  Self::default()
+ }
+
+ /// VAC に組み込みの Processor 群を登録済みの ProcessorRegistry を生成します。
+ pub fn with_builtin() -> Self {
+  let mut registry = Self::new();
+  registry
+   .register::<Command>()
+   .register::<Modify>()
+   .register::<Screenshot>()
+   .register::<Ocr>()
+   .register::<OpenAiChat>()
+   .register::<GasTranslation>()
+   .register::<Bouyomichan>()
+   .register::<CoeiroInk>()
+   .register::<OsTts>()
+   .register::<Voicevox>()
+   .register::<HttpTts>();
+  registry
+ }
+
+ /// Processor を実装した型を P::FEATURE で登録します。同じ feature が登録済みの場合は置き換えます。
+ pub fn register<P: Processor + 'static>(&mut self) -> &mut Self {
+  self.register_factory(P::FEATURE, |pc, state| {
+   Box::pin(async move {
+    let p = P::new(&pc, &state).await?;
+    Ok(Arc::new(p) as SharedProcessor)
+   })
+  })
+ }
+
+ /// 任意のファクトリーを feature で登録します。同じ feature が登録済みの場合は置き換えます。
+ pub fn register_factory<F>(&mut self, feature: &str, factory: F) -> &mut Self
+ where
+  F: Fn(ProcessorConf, SharedState) -> BoxFuture<'static, Result<SharedProcessor>> + Send + Sync + 'static,
+ {
+  let feature = feature.to_lowercase();
+  if self.factories.insert(feature.clone(), Arc::new(factory)).is_some() {
+   log::warn!("feature = {:?} の Processor は既に登録されていたため置き換えられました。", feature);
+  }
+  self
+ }
+
+ pub fn contains(&self, feature: &str) -> bool {
+  self.factories.contains_key(&feature.to_lowercase())
+ }
+
+ pub fn features(&self) -> Vec<&str> {
+  self.factories.keys().map(|k| k.as_str()).collect()
+ }
+
+ /// pc.feature に対応するファクトリーで Processor を生成します。未登録の feature の場合は None を返します。
+ pub async fn create(&self, pc: &ProcessorConf, state: &SharedState) -> Option<Result<SharedProcessor>> {
+  let feature = pc.feature.as_ref()?.to_lowercase();
+  let factory = self.factories.get(&feature)?.clone();
+  Some(factory(pc.clone(), state.clone()).await)
+ }
+}
@@ -25,7 +25,7 @@ mod ops;
 mod windows;
 
 use super::{CompletedAnd, Processor};
-use crate::{ChannelDatum, ProcessorConf, ProcessorKind, SharedProcessorConf, SharedState};
+use crate::{ChannelDatum, ProcessorConf, SharedProcessorConf, SharedState};
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
@@ -94,7 +94,7 @@ impl Debug for Target {
 impl Processor for Screenshot {
  const FEATURE: &'static str = "screenshot";
 
- async fn process(&self, _id: u64) -> Result<CompletedAnd> {
+ async fn process(&self, id: u64) -> Result<CompletedAnd> {
   log::debug!("Screenshot::process() が呼び出されました。");
 
   let conf = self.conf.read().await.clone();
@@ -103,6 +103,7 @@ impl Processor for Screenshot {
   let paths = conf.paths.clone();
   let using = self.using.clone();
   let area = self.area.clone();
+  let processor_id = conf.get_id();
 
   // 4要素ではない子要素があれば4要素になるまで None で埋めつつ複製を製造
   let crops = conf
@@ -222,9 +223,13 @@ impl Processor for Screenshot {
 
     let output_contents_json = serde_json::to_string(&output).unwrap();
     // チャンネル送信
-    let channel_datum = ChannelDatum::new(channel_to.clone(), output_contents_json).with_flag(ChannelDatum::FLAG_IS_FINAL);
+    let mut channel_datum = ChannelDatum::new(channel_to.clone(), output_contents_json).with_flag(ChannelDatum::FLAG_IS_FINAL);
     {
      let state = state.read().await;
+     // 入力の来歴を引き継ぐ
+     if let Some(source) = state.rfind_channel_datum(id).await {
+      channel_datum = channel_datum.with_source(&source, &processor_id);
+     }
      state.push_channel_datum(channel_datum).await;
     }
    }
@@ -237,7 +242,7 @@ impl Processor for Screenshot {
   self.conf.clone()
  }
 
- async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<ProcessorKind> {
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
   #[cfg(target_os = "windows")]
   let target = match (&pc.title, &pc.title_regex) {
    (Some(title), _) => Target::Title(title.clone()),
@@ -277,7 +282,7 @@ impl Processor for Screenshot {
    bail!("Screenshot が正常に設定されていません: {:?}", pc);
   }
 
-  Ok(ProcessorKind::Screenshot(p))
+  Ok(p)
  }
 
  async fn is_channel_from(&self, channel_from: &str) -> bool {
@@ -0,0 +1,599 @@
+use super::{CompletedAnd, Processor, VoiceMarkup};
+use crate::{
+ audio::DEFAULT_LIP_SYNC_FPS, Arc, AudioCache, AudioQueueHook, AudioQueueHooks, ChannelDatum, LipSyncTrack, ProcessorConf, RwLock,
+ SharedAudioQueue, SharedChannelData, SharedProcessorConf, SharedState,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use regex::Regex;
+use rodio::{Decoder, Source};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+/// 分割した文を同時に音声合成する数のデフォルト
+pub const DEFAULT_SYNTHESIS_CONCURRENCY: usize = 2;
+
+/// 音声合成エンジンの実装用のトレイトです。
+/// テキストを受け取って音声を返す部分だけを実装すると、入力の取得、 split_regex_pattern による分割、
+/// audio_file_store_path への保存、 channel_to への送出、再生キューでの再生、口パクや発話の開始/終了の送出は
+/// TtsProcessor が共通で行います。 TtsProcessor<E> を ProcessorRegistry::register すると feature から使えるようになります。
+#[async_trait]
+pub trait TtsEngine: Clone + Send + Sync + std::fmt::Debug + 'static {
+ /// Processor::FEATURE として使われる他の Processor と重複しない一意の文字列。
+ const FEATURE: &'static str;
+ /// ログに表示する音声合成エンジンの名前
+ const NAME: &'static str;
+
+ /// 設定ファイルの値にデフォルト値を補うなど、エンジンの生成前に ProcessorConf を修正します。
+ async fn fix_conf(pc: &ProcessorConf) -> Result<ProcessorConf> {
+  Ok(pc.clone())
+ }
+ async fn new(pc: &ProcessorConf) -> Result<Self>;
+ async fn is_established(&mut self, pc: &ProcessorConf) -> bool;
+ /// text を音声合成します。 pc は処理を開始した時点の ProcessorConf です。
+ async fn synthesize(&self, text: &str, pc: &ProcessorConf) -> Result<TtsAudio>;
+
+ /// synthesize が TtsAudio::Data を返すエンジンか。 false の場合は channel_to などの音声データを使う機能は使えません。
+ fn returns_audio(&self) -> bool {
+  true
+ }
+ /// synthesize が TtsAudio::Speaking を返した後、エンジン自身の再生が終わるまで待ちます。
+ async fn wait_until_spoken(&self) {}
+ /// 音声のキャッシュのキーに使う、テキスト以外で合成される音声が変わる設定 (話者、スタイル、スケールなど) を文字列にしたもの。
+ /// None の場合はキャッシュを使いません。
+ fn voice_key(&self, _pc: &ProcessorConf) -> Option<String> {
+  None
+ }
+ /// 読み上げる内容の [speaker:xxx] [style:xxx] [speed:1.3] などのタグによる上書きを pc へ反映します。
+ /// デフォルトでは *_scale 、 speaker_uuid 、数値の style_id をそのまま上書きします。
+ async fn apply_markup(&self, pc: &mut ProcessorConf, markup: &VoiceMarkup) -> Result<()> {
+  markup.apply_scales(pc);
+  if let Some(speaker) = markup.speaker.as_ref() {
+   pc.speaker_uuid = Some(speaker.clone());
+  }
+  if let Some(style) = markup.style.as_ref() {
+   pc.style_id = Some(
+    markup
+     .style_id()
+     .with_context(|| format!("style にはスタイルの ID を指定してください: {:?}", style))?,
+   );
+  }
+  Ok(())
+ }
+}
+
+/// TtsEngine::synthesize の結果
+#[derive(Debug, Clone)]
+pub enum TtsAudio {
+ /// WAV など rodio でデコードできる音声データ
+ Data(Vec<u8>),
+ /// エンジン自身が VAC を動かしている PC で再生を開始した
+ Speaking,
+ /// 棒読みちゃんなど外部のアプリケーションへ読み上げを依頼した。再生の開始や終了は分かりません。
+ Delegated,
+}
+
+/// 話者の一覧の使い回す期間
+const SPEAKERS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// エンジンから取得した話者の一覧を SPEAKERS_CACHE_TTL の間だけ使い回します。
+/// 読み上げのタグで区切った区間ごとに話者の一覧を取得し直さないよう、 TtsEngine の実装で保持して使います。
+#[derive(Debug)]
+pub struct SpeakersCache<T> {
+ speakers: Arc<RwLock<Option<(tokio::time::Instant, Arc<T>)>>>,
+}
+
+impl<T> Clone for SpeakersCache<T> {
+ fn clone(&self) -> Self {
+  SpeakersCache {
+   speakers: self.speakers.clone(),
+  }
+ }
+}
+
+impl<T> Default for SpeakersCache<T> {
+ fn default() -> Self {
+  SpeakersCache {
+   speakers: Arc::new(RwLock::new(None)),
+  }
+ }
+}
+
+impl<T> SpeakersCache<T> {
+ /// 期限内の話者の一覧があればそれを、無ければ fetch で取得して返します。
+ pub async fn get_or_fetch<F>(&self, fetch: F) -> Result<Arc<T>>
+ where
+  F: std::future::Future<Output = Result<T>>,
+ {
+  if let Some((fetched_at, speakers)) = self.speakers.read().await.as_ref() {
+   if fetched_at.elapsed() < SPEAKERS_CACHE_TTL {
+    return Ok(speakers.clone());
+   }
+  }
+  let speakers = Arc::new(fetch.await?);
+  *self.speakers.write().await = Some((tokio::time::Instant::now(), speakers.clone()));
+  Ok(speakers)
+ }
+}
+
+/// TtsEngine を使って channel_from へ入力された内容を読み上げる Processor です。
+#[derive(Debug, Clone)]
+pub struct TtsProcessor<E: TtsEngine> {
+ conf: SharedProcessorConf,
+ state: SharedState,
+ channel_data: SharedChannelData,
+ split_regex: Option<Regex>,
+ audio_queue: SharedAudioQueue,
+ engine: E,
+}
+
+#[async_trait]
+impl<E: TtsEngine> Processor for TtsProcessor<E> {
+ const FEATURE: &'static str = E::FEATURE;
+
+ async fn process(&self, id: u64) -> Result<CompletedAnd> {
+  log::debug!("{}::process() が呼び出されました。", E::NAME);
+
+  let channel_data = self.channel_data.clone();
+  let speaker = self.speaker().await;
+
+  tokio::spawn(async move {
+   // 入力を取得
+   let source_datum = {
+    let channel_data = channel_data.read().await;
+    match channel_data.iter().rev().find(|cd| cd.get_id() == id) {
+     Some(source) if source.has_flag(ChannelDatum::FLAG_IS_FINAL) => source.clone(),
+     Some(_) => {
+      log::trace!("未確定の入力なので、処理をスキップします。");
+      return Ok(());
+     },
+     None => bail!("指定された id の ChannelDatum が見つかりませんでした: {}", id),
+    }
+   };
+
+   speaker.speak(&source_datum).await;
+   Ok(())
+  });
+
+  log::trace!("{}::process() は非同期処理を開始しました。", E::NAME);
+
+  Ok(CompletedAnd::Next)
+ }
+
+ fn conf(&self) -> SharedProcessorConf {
+  self.conf.clone()
+ }
+
+ async fn prewarm_audio_cache(&self, text: &str) -> Result<usize> {
+  self.speaker().await.prewarm(text).await
+ }
+
+ async fn new(pc: &ProcessorConf, state: &SharedState) -> Result<Self> {
+  let pc = E::fix_conf(pc).await?;
+
+  let split_regex = match pc.split_regex_pattern.as_ref() {
+   Some(pattern) => Some(Regex::new(pattern).with_context(|| format!("split_regex_pattern が正規表現として不正です: {:?}", pattern))?),
+   None => None,
+  };
+
+  let mut p = TtsProcessor {
+   conf: pc.as_shared(),
+   state: state.clone(),
+   channel_data: state.read().await.channel_data.clone(),
+   split_regex,
+   audio_queue: state.read().await.audio_queues.get(pc.audio_device.as_deref()).await,
+   engine: E::new(&pc).await?,
+  };
+
+  if !p.is_established().await {
+   bail!("{} が正常に設定されていません: {:?}", E::NAME, pc);
+  }
+
+  Ok(p)
+ }
+
+ async fn is_channel_from(&self, channel_from: &str) -> bool {
+  let conf = self.conf.read().await;
+  conf.channel_from.as_ref().unwrap() == channel_from
+ }
+
+ async fn is_established(&mut self) -> bool {
+  let conf = self.conf.read().await;
+
+  if conf.channel_from.is_none() {
+   log::error!("channel_from が設定されていません。");
+   return false;
+  }
+
+  if !self.engine.returns_audio() {
+   if conf.channel_to.is_some() || conf.lip_sync_channel.is_some() || conf.audio_file_store_path.is_some() {
+    log::warn!(
+     "{} は音声データを返さないため channel_to, lip_sync_channel, audio_file_store_path は使えません。",
+     E::NAME
+    );
+   }
+  } else if conf.channel_to.is_some() {
+   log::info!(
+    "channel_to が設定されているため、合成した音声の URL が channel_to へ送信されます: channel_to={:?}",
+    conf.channel_to
+   );
+  } else if conf.audio_play_locally == Some(false) {
+   log::warn!("audio_play_locally = false ですが channel_to が設定されていないため、合成した音声はどこにも出力されません。");
+  }
+
+  // 発話の開始/終了は VAC 側で再生した時に送るため
+  if self.engine.returns_audio() && conf.audio_play_locally == Some(false) && conf.speech_event_channel.is_some() {
+   log::warn!("audio_play_locally = false では VAC 側で再生しないため speech_event_channel は使えません。");
+  }
+
+  self.engine.is_established(&conf).await
+ }
+}
+
+impl<E: TtsEngine> TtsProcessor<E> {
+ async fn speaker(&self) -> Speaker<E> {
+  let conf = self.conf.read().await.clone();
+  let audio_cache = match conf.audio_cache.unwrap_or(true) {
+   true => self.state.read().await.audio_cache.clone(),
+   false => None,
+  };
+  Speaker {
+   state: self.state.clone(),
+   conf,
+   split_regex: self.split_regex.clone(),
+   audio_queue: self.audio_queue.clone(),
+   audio_cache,
+   engine: self.engine.clone(),
+  }
+ }
+}
+
+/// process ごとに TtsProcessor から必要なものを複製して非同期に読み上げる処理です。
+struct Speaker<E: TtsEngine> {
+ state: SharedState,
+ conf: ProcessorConf,
+ split_regex: Option<Regex>,
+ audio_queue: SharedAudioQueue,
+ audio_cache: Option<AudioCache>,
+ engine: E,
+}
+
+impl<E: TtsEngine> Speaker<E> {
+ /// split_regex_pattern が設定されていれば分割します。
+ fn split(&self, content: &str) -> Vec<String> {
+  match self.split_regex.as_ref() {
+   // 正規表現で分割
+   Some(split_regex) => split_regex
+    .split(content)
+    .map(|s| s.trim().to_string())
+    .filter(|s| !s.is_empty())
+    .collect::<Vec<String>>(),
+   // 分割しない
+   None => vec![content.to_string()],
+  }
+ }
+
+ /// 読み上げのタグで区切った区間ごとに設定を上書きし、さらに split_regex_pattern で分割した (内容, 設定) の一覧を作ります。
+ async fn chunks(&self, content: &str) -> Vec<(String, ProcessorConf)> {
+  let mut chunks = vec![];
+  for (markup, text) in VoiceMarkup::parse(content) {
+   let mut conf = self.conf.clone();
+   if !markup.is_empty() {
+    if let Err(e) = self.engine.apply_markup(&mut conf, &markup).await {
+     log::warn!(
+      "{} で読み上げのタグを反映できなかったため Processor の設定で読み上げます: {:?} {:?}",
+      E::NAME,
+      markup,
+      e
+     );
+     conf = self.conf.clone();
+    }
+   }
+   chunks.extend(self.split(&text).into_iter().map(|text| (text, conf.clone())));
+  }
+  chunks
+ }
+
+ /// 音声のキャッシュのキー。キャッシュを使わない場合は None です。
+ fn cache_key(&self, text: &str, conf: &ProcessorConf) -> Option<String> {
+  self.audio_cache.as_ref()?;
+  let voice_key = self.engine.voice_key(conf)?;
+  Some(AudioCache::key(E::FEATURE, &voice_key, text))
+ }
+
+ /// キャッシュがあればキャッシュから、無ければエンジンで音声合成してキャッシュへ保存します。
+ async fn synthesize(&self, text: &str, conf: &ProcessorConf) -> Result<TtsAudio> {
+  let (audio_cache, key) = match (self.audio_cache.as_ref(), self.cache_key(text, conf)) {
+   (Some(audio_cache), Some(key)) => (audio_cache, key),
+   _ => return self.engine.synthesize(text, conf).await,
+  };
+
+  if let Some(data) = audio_cache.get(&key).await {
+   log::debug!("{} の音声をキャッシュから取得しました: {:?}", E::NAME, text);
+   return Ok(TtsAudio::Data(data));
+  }
+
+  let audio = self.engine.synthesize(text, conf).await?;
+  if let TtsAudio::Data(data) = &audio {
+   if let Err(e) = audio_cache.insert(&key, data).await {
+    log::warn!("音声のキャッシュへの保存に失敗しました: {:?}", e);
+   }
+  }
+  Ok(audio)
+ }
+
+ /// 読み上げずに音声合成だけを行い、キャッシュへ保存します。新たに保存した数を返します。
+ async fn prewarm(&self, content: &str) -> Result<usize> {
+  if self.audio_cache.is_none() || self.engine.voice_key(&self.conf).is_none() {
+   return Ok(0);
+  }
+
+  let mut count = 0;
+  for (text, conf) in self.chunks(content).await {
+   let key = match self.cache_key(&text, &conf) {
+    Some(key) => key,
+    None => continue,
+   };
+   if self.audio_cache.as_ref().unwrap().contains(&key).await {
+    continue;
+   }
+   if let TtsAudio::Data(_) = self.synthesize(&text, &conf).await? {
+    count += 1;
+   }
+  }
+  Ok(count)
+ }
+
+ async fn speak(&self, source_datum: &ChannelDatum) {
+  let processor_id = self.conf.get_id();
+  let play_locally = self.conf.audio_play_locally.unwrap_or(true);
+
+  // 字幕用にタグを除去した内容を送出
+  if let Some(subtitle_channel) = self.conf.subtitle_channel.as_ref() {
+   let cd = ChannelDatum::new(subtitle_channel.clone(), VoiceMarkup::strip(&source_datum.content))
+    .with_flag(ChannelDatum::FLAG_IS_FINAL)
+    .with_source(source_datum, &processor_id);
+   self.state.read().await.push_channel_datum(cd).await;
+  }
+
+  let chunks = self.chunks(&source_datum.content).await;
+  let texts_len = chunks.len();
+
+  // 音声データを返すエンジンでは前の文の再生中に後ろの文を先行して音声合成する。
+  // エンジン自身が再生するものや外部へ依頼するものは順番が入れ替わらないよう 1 つずつ。
+  let concurrency = match self.engine.returns_audio() {
+   true => self.conf.synthesis_concurrency.unwrap_or(DEFAULT_SYNTHESIS_CONCURRENCY).max(1),
+   false => 1,
+  };
+
+  // 分割した音声の間に他の発話の音声が割り込まないよう、全て追加し終えるまで保持
+  let _producer = self.audio_queue.lock_producer().await;
+
+  // buffered は concurrency 個まで同時に音声合成し、結果は分割した順に返す
+  let mut synthesized = futures::stream::iter(chunks.into_iter().enumerate())
+   .map(|(num, (text, conf))| async move {
+    log::debug!("{} に音声合成をリクエストします。 {} / {}", E::NAME, num + 1, texts_len);
+    let audio = self.synthesize(&text, &conf).await;
+    (num, text, audio)
+   })
+   .buffered(concurrency);
+
+  while let Some((num, text, audio)) = synthesized.next().await {
+   let audio = match audio {
+    Ok(audio) => audio,
+    Err(e) => {
+     log::error!("{} の音声合成に失敗しました: {:?}", E::NAME, e);
+     continue;
+    },
+   };
+
+   match audio {
+    TtsAudio::Data(data) => {
+     log::debug!("{} からの音声合成データの取得に成功しました。", E::NAME);
+
+     if let Some(path) = self.conf.audio_file_store_path.as_ref() {
+      let path = audio_file_path(path, num, texts_len, audio_format(&data).1);
+      log::debug!("{} からの音声合成データを {} に保存します。", E::NAME, path.display());
+      if let Err(e) = tokio::fs::write(&path, &data).await {
+       log::error!("音声合成データの保存に失敗しました: {} {:?}", path.display(), e);
+      }
+     }
+
+     // channel_to が設定されていればブラウザーなどで再生できるよう送出
+     if let Some(channel_to) = self.conf.channel_to.as_ref() {
+      push_synthesized_audio(&self.state, channel_to, source_datum, &processor_id, &text, data.clone()).await;
+     }
+
+     if !play_locally {
+      continue;
+     }
+
+     // ペイロードを WAV などとして再生
+     let audio = match Decoder::new(Cursor::new(data)) {
+      Ok(audio) => audio,
+      Err(e) => {
+       log::error!("{} からの音声合成データのデコードに失敗しました: {:?}", E::NAME, e);
+       continue;
+      },
+     };
+     append_synthesized_audio(&self.state, &self.audio_queue, &self.conf, source_datum, &text, audio).await;
+
+     log::debug!(
+      "{} からの音声合成データを再生キューへ追加しました。 {} / {}",
+      E::NAME,
+      num + 1,
+      texts_len
+     );
+    },
+    TtsAudio::Speaking => {
+     if let Some(speech_event_channel) = self.conf.speech_event_channel.as_ref() {
+      push_speech_event(
+       &self.state,
+       speech_event_channel,
+       ChannelDatum::FLAG_SPEECH_START,
+       source_datum,
+       &processor_id,
+       &text,
+      )
+      .await;
+     }
+     // エンジンの再生が終わるまで待機
+     self.engine.wait_until_spoken().await;
+     if let Some(speech_event_channel) = self.conf.speech_event_channel.as_ref() {
+      push_speech_event(
+       &self.state,
+       speech_event_channel,
+       ChannelDatum::FLAG_SPEECH_END,
+       source_datum,
+       &processor_id,
+       &text,
+      )
+      .await;
+     }
+    },
+    TtsAudio::Delegated => {
+     log::debug!("{} へ読み上げを依頼しました。 {} / {}", E::NAME, num + 1, texts_len);
+    },
+   }
+  }
+ }
+}
+
+/// 音声データの先頭のバイト列から (MIME, 拡張子) を推定します。分からない場合は WAV とみなします。
+fn audio_format(data: &[u8]) -> (&'static str, &'static str) {
+ match data {
+  [b'O', b'g', b'g', b'S', ..] => ("audio/ogg", "ogg"),
+  [b'f', b'L', b'a', b'C', ..] => ("audio/flac", "flac"),
+  [b'I', b'D', b'3', ..] => ("audio/mpeg", "mp3"),
+  [0xff, b, ..] if b & 0xe0 == 0xe0 => ("audio/mpeg", "mp3"),
+  _ => ("audio/wav", "wav"),
+ }
+}
+
+/// audio_file_store_path に分割した番号と分割数と拡張子を付けたパスを作ります。
+/// path に {T} が含まれていたら ISO8601 日時文字列から : と - を除去して置換します。
+fn audio_file_path(audio_file_store_path: &str, num: usize, len: usize, extension: &str) -> PathBuf {
+ let path = format!("{}_{}_{}.{}", audio_file_store_path, num, len, extension);
+ match path.contains("{T}") {
+  true => {
+   let t = chrono::Utc::now().to_rfc3339().replace(":", "").replace("-", "");
+   PathBuf::from(path.replace("{T}", &t))
+  },
+  false => PathBuf::from(path),
+ }
+}
+
+/// 音声合成した WAV などを /audio/{id} で取得できるようにし、その URL を metadata の audio_url に付けて channel_to へ送出します。
+/// VAC とは別の PC のブラウザーソースなどで字幕と一緒に音声を再生するために使います。
+async fn push_synthesized_audio(
+ state: &SharedState,
+ channel_to: &str,
+ source: &ChannelDatum,
+ processor_id: &str,
+ content: &str,
+ audio: Vec<u8>,
+) {
+ let state = state.read().await;
+ let mime = audio_format(&audio).0;
+ let audio_url = state.audio_clips.insert(audio, mime).await;
+ let cd = ChannelDatum::new(channel_to.to_string(), content.to_string())
+  .with_flag(ChannelDatum::FLAG_IS_FINAL)
+  .with_source(source, processor_id)
+  .with_audio_url(&audio_url);
+ log::debug!(
+  "合成した音声を {} で取得できるようにして {:?} へ送出します。",
+  audio_url,
+  channel_to
+ );
+ state.push_channel_datum(cd).await;
+}
+
+/// 合成した音声を再生キューへ追加し、再生キューの中での ID を返します。
+/// lip_sync_channel が設定されていれば、再生を開始した時に口パク用の音量の推移を JSON で lip_sync_channel へ送出します。
+/// speech_event_channel が設定されていれば、再生の開始と終了の時に speech_start / speech_end を送出します。
+async fn append_synthesized_audio<S>(
+ state: &SharedState,
+ audio_queue: &SharedAudioQueue,
+ pc: &ProcessorConf,
+ source: &ChannelDatum,
+ content: &str,
+ audio: S,
+) -> u64
+where
+ S: Source + Send + 'static,
+{
+ let processor_id = pc.get_id();
+ let mut on_start_hooks: Vec<AudioQueueHook> = vec![];
+ let mut hooks = AudioQueueHooks::default();
+
+ let audio: Box<dyn Source + Send> = match pc.lip_sync_channel.clone() {
+  Some(lip_sync_channel) => {
+   let (audio, track) = LipSyncTrack::analyze(audio, pc.lip_sync_fps.unwrap_or(DEFAULT_LIP_SYNC_FPS), content);
+   let state = state.clone();
+   let source_datum = source.clone();
+   let id = processor_id.clone();
+   on_start_hooks.push(Box::new(move |entry| {
+    let track = LipSyncTrack {
+     started_at: entry.started_at,
+     audio_queue_id: Some(entry.id),
+     ..track
+    };
+    tokio::spawn(async move {
+     let content = match serde_json::to_string(&track) {
+      Ok(content) => content,
+      Err(e) => {
+       log::error!("口パク用の音量の推移を JSON にできませんでした: {:?}", e);
+       return;
+      },
+     };
+     let cd = ChannelDatum::new(lip_sync_channel, content)
+      .with_flag(ChannelDatum::FLAG_IS_FINAL)
+      .with_source(&source_datum, &id);
+     state.read().await.push_channel_datum(cd).await;
+    });
+   }));
+   Box::new(audio)
+  },
+  None => Box::new(audio),
+ };
+
+ if let Some(speech_event_channel) = pc.speech_event_channel.clone() {
+  let speech_event_hook = |flag: &'static str| -> AudioQueueHook {
+   let state = state.clone();
+   let channel = speech_event_channel.clone();
+   let source_datum = source.clone();
+   let id = processor_id.clone();
+   Box::new(move |entry| {
+    tokio::spawn(async move {
+     push_speech_event(&state, &channel, flag, &source_datum, &id, &entry.content).await;
+    });
+   })
+  };
+  on_start_hooks.push(speech_event_hook(ChannelDatum::FLAG_SPEECH_START));
+  hooks.on_end = Some(speech_event_hook(ChannelDatum::FLAG_SPEECH_END));
+ }
+
+ if !on_start_hooks.is_empty() {
+  hooks.on_start = Some(Box::new(move |entry| {
+   for hook in on_start_hooks {
+    hook(entry.clone());
+   }
+  }));
+ }
+
+ audio_queue
+  .append_with_hooks(audio, &processor_id, Some(source.get_id()), content, hooks)
+  .await
+}
+
+/// 音声の再生の開始または終了を flag (speech_start / speech_end) 付きで channel へ送出します。
+/// metadata.source_id で読み上げた元の入力と紐付けられます。
+async fn push_speech_event(state: &SharedState, channel: &str, flag: &str, source: &ChannelDatum, processor_id: &str, content: &str) {
+ log::debug!("{} を {:?} へ送出します: {:?}", flag, channel, source.get_id());
+ let cd = ChannelDatum::new(channel.to_string(), content.to_string())
+  .with_flag(ChannelDatum::FLAG_IS_FINAL)
+  .with_flag(flag)
+  .with_source(source, processor_id);
+ state.read().await.push_channel_datum(cd).await;
+}
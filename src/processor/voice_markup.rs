@@ -0,0 +1,112 @@
+use crate::ProcessorConf;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// 読み上げる内容に埋め込まれた [style:angry] などのタグによる声の設定の上書きです。
+/// タグより後ろの内容は次に同じ名前のタグが現れるまでその設定で読み上げます。 [style:] のように値を空にすると Processor の設定に戻ります。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoiceMarkup {
+ /// [speaker:xxx] 話者の UUID または名前
+ pub speaker: Option<String>,
+ /// [style:xxx] スタイルの ID または名前
+ pub style: Option<String>,
+ /// [speed:1.3]
+ pub speed: Option<f64>,
+ /// [pitch:0.1]
+ pub pitch: Option<f64>,
+ /// [intonation:1.2]
+ pub intonation: Option<f64>,
+ /// [volume:0.8]
+ pub volume: Option<f64>,
+}
+
+/// [style:angry] などのタグ
+static VOICE_MARKUP: LazyLock<Regex> =
+ LazyLock::new(|| Regex::new(r"\[(speaker|style|speed|pitch|intonation|volume):([^\[\]]*)\]").unwrap());
+
+impl VoiceMarkup {
+ /// content をタグで区切り、 (その区間の設定, タグを除去した内容) の一覧にします。内容が空の区間は含みません。
+ pub fn parse(content: &str) -> Vec<(VoiceMarkup, String)> {
+  let mut segments = vec![];
+  let mut markup = VoiceMarkup::default();
+  let mut last = 0;
+
+  for captures in VOICE_MARKUP.captures_iter(content) {
+   let tag = captures.get(0).unwrap();
+   push_segment(&mut segments, &markup, &content[last..tag.start()]);
+   last = tag.end();
+
+   if let Err(e) = markup.set(&captures[1], captures[2].trim()) {
+    log::warn!("読み上げのタグを無視します: {} {:?}", tag.as_str(), e);
+   }
+  }
+  push_segment(&mut segments, &markup, &content[last..]);
+
+  segments
+ }
+
+ /// content からタグを除去します。字幕の表示などに使います。
+ pub fn strip(content: &str) -> String {
+  VOICE_MARKUP.replace_all(content, "").trim().to_string()
+ }
+
+ pub fn is_empty(&self) -> bool {
+  self == &VoiceMarkup::default()
+ }
+
+ fn set(&mut self, name: &str, value: &str) -> Result<()> {
+  let text = match value.is_empty() {
+   true => None,
+   false => Some(value.to_string()),
+  };
+  let number = || -> Result<Option<f64>> {
+   match value.is_empty() {
+    true => Ok(None),
+    false => Ok(Some(
+     value
+      .parse::<f64>()
+      .with_context(|| format!("{} の値が数値ではありません: {:?}", name, value))?,
+    )),
+   }
+  };
+  match name {
+   "speaker" => self.speaker = text,
+   "style" => self.style = text,
+   "speed" => self.speed = number()?,
+   "pitch" => self.pitch = number()?,
+   "intonation" => self.intonation = number()?,
+   "volume" => self.volume = number()?,
+   _ => unreachable!(),
+  }
+  Ok(())
+ }
+
+ /// speed, pitch, intonation, volume を pc の *_scale へ上書きします。
+ pub fn apply_scales(&self, pc: &mut ProcessorConf) {
+  if self.speed.is_some() {
+   pc.speed_scale = self.speed;
+  }
+  if self.pitch.is_some() {
+   pc.pitch_scale = self.pitch;
+  }
+  if self.intonation.is_some() {
+   pc.intonation_scale = self.intonation;
+  }
+  if self.volume.is_some() {
+   pc.volume_scale = self.volume;
+  }
+ }
+
+ /// style が数値ならスタイルの ID として返します。
+ pub fn style_id(&self) -> Option<i64> {
+  self.style.as_ref().and_then(|style| style.parse::<i64>().ok())
+ }
+}
+
+fn push_segment(segments: &mut Vec<(VoiceMarkup, String)>, markup: &VoiceMarkup, text: &str) {
+ let text = text.trim();
+ if !text.is_empty() {
+  segments.push((markup.clone(), text.to_string()));
+ }
+}
@@ -0,0 +1,286 @@
+use super::{SpeakersCache, TtsAudio, TtsEngine, TtsProcessor, VoiceMarkup};
+use crate::ProcessorConf;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// VOICEVOX 互換 (VOICEVOX, SHAREVOX, AivisSpeech など) のエンジンの API で音声合成する Processor です。
+pub type Voicevox = TtsProcessor<VoicevoxEngine>;
+
+/// VOICEVOX の既定の API の URL。 SHAREVOX は http://127.0.0.1:50025 、 AivisSpeech は http://127.0.0.1:10101 です。
+pub const DEFAULT_VOICEVOX_API_URL: &str = "http://127.0.0.1:50021";
+
+#[derive(Debug, Clone)]
+pub struct VoicevoxEngine {
+ api_url: String,
+ style_id: i64,
+ /// 読み上げのタグで話者やスタイルを名前から探すための /speakers の一覧
+ speakers: SpeakersCache<Vec<VoicevoxSpeaker>>,
+ client: reqwest::Client,
+}
+
+#[async_trait]
+impl TtsEngine for VoicevoxEngine {
+ const FEATURE: &'static str = "voicevox";
+ const NAME: &'static str = "VOICEVOX";
+
+ async fn fix_conf(original: &ProcessorConf) -> Result<ProcessorConf> {
+  let mut fixed = original.clone();
+
+  if fixed.api_url.is_none() {
+   log::warn!("api_url が設定されていないため、 {} にデフォルトします。", DEFAULT_VOICEVOX_API_URL);
+   fixed.api_url = Some(DEFAULT_VOICEVOX_API_URL.to_string());
+  }
+
+  if fixed.style_id.is_none() {
+   log::warn!("style_id が設定されていません。 API でデフォルトロードを試みます。");
+   let api_url = fixed.api_url.as_ref().unwrap();
+   match Voicevox::get_speakers(api_url).await {
+    Ok(speakers) => match speakers.iter().find_map(|s| s.styles.first().map(|style| (s, style))) {
+     Some((speaker, style)) => {
+      log::warn!("style_id を {}: {} ( {} ) にデフォルトします。", style.id, style.name, speaker.name);
+      fixed.style_id = Some(style.id);
+     },
+     None => bail!("VOICEVOX の応答に Style 情報が存在しませんでした。"),
+    },
+    Err(e) => bail!(
+     "VOICEVOX と API 通信できなかったためデフォルトロードに失敗しました。VOICEVOX の動作状態を確認してください。 {:?}",
+     e
+    ),
+   }
+  }
+
+  Ok(fixed)
+ }
+
+ async fn new(pc: &ProcessorConf) -> Result<Self> {
+  Ok(VoicevoxEngine {
+   api_url: pc.api_url.as_ref().unwrap().trim_end_matches('/').to_string(),
+   style_id: pc.style_id.unwrap(),
+   speakers: SpeakersCache::default(),
+   client: reqwest::Client::new(),
+  })
+ }
+
+ async fn is_established(&mut self, conf: &ProcessorConf) -> bool {
+  log::info!(
+   "VOICEVOX は正常に設定されています: channel: {:?} api_url: {:?} style_id: {:?} speed: {:?} pitch: {:?} intonation: {:?} volume: {:?}",
+   conf.channel_from,
+   self.api_url,
+   self.style_id,
+   conf.speed_scale,
+   conf.pitch_scale,
+   conf.intonation_scale,
+   conf.volume_scale,
+  );
+  true
+ }
+
+ async fn synthesize(&self, text: &str, pc: &ProcessorConf) -> Result<TtsAudio> {
+  let speaker = pc.style_id.unwrap_or(self.style_id).to_string();
+
+  // audio_query で合成用のクエリーを作成
+  let mut query = self
+   .client
+   .post(format!("{}/audio_query", self.api_url))
+   .query(&[("text", text), ("speaker", speaker.as_str())])
+   .send()
+   .await?
+   .error_for_status()?
+   .json::<serde_json::Value>()
+   .await?;
+
+  // 設定されたパラメーターでクエリーを上書き
+  let scales = [
+   ("speedScale", pc.speed_scale),
+   ("pitchScale", pc.pitch_scale),
+   ("intonationScale", pc.intonation_scale),
+   ("volumeScale", pc.volume_scale),
+   ("prePhonemeLength", pc.pre_phoneme_length),
+   ("postPhonemeLength", pc.post_phoneme_length),
+  ];
+  for (key, value) in scales {
+   if let Some(value) = value {
+    query[key] = serde_json::json!(value);
+   }
+  }
+  if let Some(output_sampling_rate) = pc.output_sampling_rate {
+   query["outputSamplingRate"] = serde_json::json!(output_sampling_rate);
+  }
+
+  // synthesis で WAV を取得
+  let wav = self
+   .client
+   .post(format!("{}/synthesis", self.api_url))
+   .query(&[("speaker", speaker.as_str())])
+   .json(&query)
+   .send()
+   .await?
+   .error_for_status()?
+   .bytes()
+   .await?;
+
+  Ok(TtsAudio::Data(wav.to_vec()))
+ }
+
+ async fn apply_markup(&self, pc: &mut ProcessorConf, markup: &VoiceMarkup) -> Result<()> {
+  markup.apply_scales(pc);
+  if markup.speaker.is_none() && markup.style.is_none() {
+   return Ok(());
+  }
+
+  // 話者やスタイルは名前でも指定できるよう /speakers から探す
+  let speakers = self.speakers.get_or_fetch(Voicevox::get_speakers(&self.api_url)).await?;
+  let current_style_id = pc.style_id.unwrap_or(self.style_id);
+  let speaker = match markup.speaker.as_ref() {
+   Some(speaker) => speakers
+    .iter()
+    .find(|s| &s.speaker_uuid == speaker || &s.name == speaker)
+    .with_context(|| format!("speaker の話者が見つかりませんでした: {:?}", speaker))?,
+   None => speakers
+    .iter()
+    .find(|s| s.styles.iter().any(|style| style.id == current_style_id))
+    .with_context(|| format!("style_id {} の話者が見つかりませんでした。", current_style_id))?,
+  };
+  let style = match markup.style.as_ref() {
+   Some(style) => speaker
+    .styles
+    .iter()
+    .find(|s| &s.id.to_string() == style || &s.name == style)
+    .with_context(|| format!("{} に style のスタイルが見つかりませんでした: {:?}", speaker.name, style))?,
+   None => speaker
+    .styles
+    .first()
+    .with_context(|| format!("{} にスタイルが存在しませんでした。", speaker.name))?,
+  };
+  pc.style_id = Some(style.id);
+  Ok(())
+ }
+
+ fn voice_key(&self, pc: &ProcessorConf) -> Option<String> {
+  let voice = serde_json::json!({
+   "api_url": self.api_url,
+   "style_id": pc.style_id.unwrap_or(self.style_id),
+   "speed_scale": pc.speed_scale,
+   "pitch_scale": pc.pitch_scale,
+   "intonation_scale": pc.intonation_scale,
+   "volume_scale": pc.volume_scale,
+   "pre_phoneme_length": pc.pre_phoneme_length,
+   "post_phoneme_length": pc.post_phoneme_length,
+   "output_sampling_rate": pc.output_sampling_rate,
+  });
+  Some(voice.to_string())
+ }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoicevoxSpeaker {
+ pub name: String,
+ pub speaker_uuid: String,
+ pub styles: Vec<VoicevoxSpeakerStyle>,
+ pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoicevoxSpeakerStyle {
+ pub name: String,
+ pub id: i64,
+}
+
+impl Voicevox {
+ /// api_url の VOICEVOX 互換のエンジンから /speakers で話者とスタイルの一覧を取得します。
+ pub async fn get_speakers(api_url: &str) -> Result<Vec<VoicevoxSpeaker>> {
+  let url = format!("{}/speakers", api_url.trim_end_matches('/'));
+  let res = reqwest::get(url).await?.error_for_status()?;
+  Ok(res.json::<Vec<VoicevoxSpeaker>>().await?)
+ }
+}
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use actix_web::{web, App, HttpResponse, HttpServer};
+ use std::collections::HashMap;
+
+ /// /audio_query は受け取った speaker と text を含むクエリーを、 /synthesis は受け取った speaker とクエリーを音声データの代わりに返す
+ /// VOICEVOX の代わりのサーバーを起動して URL を返します。 speaker が 500 の場合はエラーを返します。
+ fn start_stub_server() -> String {
+  let server = HttpServer::new(|| {
+   App::new()
+    .route(
+     "/audio_query",
+     web::post().to(|query: web::Query<HashMap<String, String>>| async move {
+      match query["speaker"].as_str() {
+       "500" => HttpResponse::InternalServerError().finish(),
+       _ => HttpResponse::Ok().json(serde_json::json!({
+        "speaker": query["speaker"],
+        "text": query["text"],
+        "speedScale": 1.0,
+        "pitchScale": 0.0,
+       })),
+      }
+     }),
+    )
+    .route(
+     "/synthesis",
+     web::post().to(
+      |query: web::Query<HashMap<String, String>>, body: web::Json<serde_json::Value>| async move {
+       HttpResponse::Ok().json(serde_json::json!({ "speaker": query["speaker"], "query": body.into_inner() }))
+      },
+     ),
+    )
+  })
+  .workers(1)
+  .bind(("127.0.0.1", 0))
+  .unwrap();
+  let url = format!("http://{}", server.addrs()[0]);
+  actix_web::rt::spawn(server.run());
+  url
+ }
+
+ fn conf(api_url: &str, style_id: i64) -> ProcessorConf {
+  ProcessorConf {
+   api_url: Some(api_url.to_string()),
+   style_id: Some(style_id),
+   ..Default::default()
+  }
+ }
+
+ #[actix_web::test]
+ async fn synthesize_sends_speaker_text_and_scales() {
+  let api_url = start_stub_server();
+  let mut pc = conf(&api_url, 3);
+  let engine = VoicevoxEngine::new(&pc).await.unwrap();
+  pc.speed_scale = Some(1.3);
+  pc.volume_scale = Some(0.8);
+  pc.output_sampling_rate = Some(24000);
+
+  let data = match engine.synthesize("こんにちは", &pc).await.unwrap() {
+   TtsAudio::Data(data) => data,
+   audio => panic!("音声データが返されませんでした: {:?}", audio),
+  };
+  let synthesis = serde_json::from_slice::<serde_json::Value>(&data).unwrap();
+
+  assert_eq!(synthesis["speaker"], "3");
+  let query = &synthesis["query"];
+  // audio_query へ送った speaker と text
+  assert_eq!(query["speaker"], "3");
+  assert_eq!(query["text"], "こんにちは");
+  // 設定された値だけが上書きされる
+  assert_eq!(query["speedScale"], 1.3);
+  assert_eq!(query["volumeScale"], 0.8);
+  assert_eq!(query["outputSamplingRate"], 24000);
+  assert_eq!(query["pitchScale"], 0.0);
+ }
+
+ #[actix_web::test]
+ async fn synthesize_returns_http_error() {
+  let api_url = start_stub_server();
+  let pc = conf(&api_url, 500);
+  let engine = VoicevoxEngine::new(&pc).await.unwrap();
+
+  let e = engine.synthesize("こんにちは", &pc).await.unwrap_err();
+  let status = e.downcast_ref::<reqwest::Error>().and_then(|e| e.status());
+  assert_eq!(status, Some(reqwest::StatusCode::INTERNAL_SERVER_ERROR));
+ }
+}
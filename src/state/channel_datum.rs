@@ -1,7 +1,7 @@
 use crate::{Arc, RwLock};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -9,9 +9,58 @@ pub struct ChannelDatum {
  pub channel: String,
  pub content: String,
  pub flags: HashSet<String>,
- #[serde(deserialize_with = "deserialize_and_reset_id_counter")]
+ #[serde(deserialize_with = "deserialize_and_advance_id_counter")]
  id: u64,
  datetime: DateTime<Utc>,
+ /// この ChannelDatum が生成されるまでに経由した元の ChannelDatum の ID と Processor の ID の履歴(古い順)
+ #[serde(default)]
+ provenance: Vec<ChannelDatumHop>,
+ /// 元になった ChannelDatum や言語などの付加情報
+ #[serde(default)]
+ pub metadata: ChannelDatumMetadata,
+}
+
+/// ChannelDatum の来歴の1段分
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct ChannelDatumHop {
+ /// 元になった ChannelDatum の ID
+ pub id: u64,
+ /// 元になった ChannelDatum を処理した Processor の ID
+ pub processor: String,
+}
+
+/// ChannelDatum の付加情報です。
+/// オーバーレイや後続の Processor は flags や content の文字列を解析せずにこれらの値を参照できます。
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChannelDatumMetadata {
+ /// 元になった ChannelDatum の ID
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub source_id: Option<u64>,
+ /// 元になった ChannelDatum のチャンネル名
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub source_channel: Option<String>,
+ /// この ChannelDatum を生成または最後に更新した Processor の ID
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub processor: Option<String>,
+ /// 内容の言語 (例: "ja", "en")
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub lang: Option<String>,
+ /// 翻訳などで変換される前の内容の言語
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub source_lang: Option<String>,
+ /// 発言者 (例: Twitch のユーザー名)
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub author: Option<String>,
+ /// modify などで内容が上書きされた回数
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub revision: Option<u32>,
+ /// 内容を音声合成した音声を取得できる URL (例: "/audio/12")。一定時間が過ぎると取得できなくなります。
+ #[serde(skip_serializing_if = "Option::is_none")]
+ pub audio_url: Option<String>,
+ /// その他の任意の付加情報
+ #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+ pub extra: BTreeMap<String, String>,
 }
 
 pub type ChannelData = VecDeque<ChannelDatum>;
@@ -21,12 +70,21 @@ static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 impl ChannelDatum {
  pub const FLAG_IS_FINAL: &'static str = "is_final";
+ /// 音声の再生を開始したことを表すフラグ
+ pub const FLAG_SPEECH_START: &'static str = "speech_start";
+ /// 音声の再生が終わったことを表すフラグ
+ pub const FLAG_SPEECH_END: &'static str = "speech_end";
  pub const DATA_URLS: &'static str = "data_urls";
 
  pub fn reset_id_counter(id: u64) {
   ID_COUNTER.store(id, Ordering::Relaxed);
  }
 
+ /// 読み込んだ ChannelDatum の ID と重複しないよう、採番を id まで進めます。巻き戻すことはありません。
+ pub fn advance_id_counter(id: u64) {
+  ID_COUNTER.fetch_max(id, Ordering::Relaxed);
+ }
+
  pub fn get_last_id() -> u64 {
   ID_COUNTER.load(Ordering::Relaxed)
  }
@@ -38,6 +96,8 @@ impl ChannelDatum {
    flags: HashSet::new(),
    id: ID_COUNTER.fetch_add(1, Ordering::Relaxed) + 1,
    datetime: Utc::now(),
+   provenance: vec![],
+   metadata: ChannelDatumMetadata::default(),
   }
  }
 
@@ -48,6 +108,8 @@ impl ChannelDatum {
    flags: channel_datum.flags,
    id: ID_COUNTER.fetch_add(1, Ordering::Relaxed) + 1,
    datetime: Utc::now(),
+   provenance: channel_datum.provenance,
+   metadata: channel_datum.metadata,
   }
  }
 
@@ -59,6 +121,74 @@ impl ChannelDatum {
   self.datetime
  }
 
+ pub fn get_provenance(&self) -> &Vec<ChannelDatumHop> {
+  &self.provenance
+ }
+
+ /// 来歴に含まれる Processor の処理の段数
+ pub fn get_hops(&self) -> usize {
+  self.provenance.len()
+ }
+
+ /// 来歴に processor の ID が含まれていれば true
+ pub fn has_visited(&self, processor: &str) -> bool {
+  self.provenance.iter().any(|hop| hop.processor == processor)
+ }
+
+ /// source を processor が処理した結果として、 source の来歴を引き継ぎ1段追加します。
+ pub fn with_source(mut self, source: &ChannelDatum, processor: &str) -> Self {
+  self.provenance = source.provenance.clone();
+  self.metadata.source_channel = Some(source.channel.clone());
+  self.with_hop(source.id, processor)
+ }
+
+ /// 来歴の末尾に1段追加します。
+ pub fn with_hop(mut self, source_id: u64, processor: &str) -> Self {
+  self.provenance.push(ChannelDatumHop {
+   id: source_id,
+   processor: processor.to_string(),
+  });
+  self.metadata.source_id = Some(source_id);
+  self.metadata.processor = Some(processor.to_string());
+  self
+ }
+
+ pub fn with_lang(mut self, lang: &str) -> Self {
+  self.metadata.lang = Some(lang.to_string());
+  self
+ }
+
+ pub fn with_source_lang(mut self, source_lang: &str) -> Self {
+  self.metadata.source_lang = Some(source_lang.to_string());
+  self
+ }
+
+ pub fn with_author(mut self, author: &str) -> Self {
+  self.metadata.author = Some(author.to_string());
+  self
+ }
+
+ pub fn with_audio_url(mut self, audio_url: &str) -> Self {
+  self.metadata.audio_url = Some(audio_url.to_string());
+  self
+ }
+
+ pub fn with_extra(mut self, key: &str, value: &str) -> Self {
+  self.metadata.extra.insert(key.to_string(), value.to_string());
+  self
+ }
+
+ pub fn with_metadata(mut self, metadata: ChannelDatumMetadata) -> Self {
+  self.metadata = metadata;
+  self
+ }
+
+ /// processor が内容をその場で上書きした際に revision を1つ進めます。
+ pub fn update_revision(&mut self, processor: &str) {
+  self.metadata.revision = Some(self.metadata.revision.unwrap_or_default() + 1);
+  self.metadata.processor = Some(processor.to_string());
+ }
+
  pub fn with_channel(mut self, channel: String) -> Self {
   self.channel = channel;
   self
@@ -86,11 +216,12 @@ impl ChannelDatum {
  }
 }
 
-fn deserialize_and_reset_id_counter<'de, D>(deserializer: D) -> Result<u64, D::Error>
+// 読み込みの途中や再生のための読み込みで採番が巻き戻り、 ID が重複しないよう進めるだけにする
+fn deserialize_and_advance_id_counter<'de, D>(deserializer: D) -> Result<u64, D::Error>
 where
  D: serde::Deserializer<'de>,
 {
  let id = u64::deserialize(deserializer)?;
- ID_COUNTER.store(id, Ordering::Relaxed);
+ ChannelDatum::advance_id_counter(id);
  Ok(id)
 }
@@ -0,0 +1,47 @@
+use super::ChannelDatum;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub const DEFAULT_CHANNEL_EVENT_BUS_CAPACITY: usize = 1024;
+
+pub type ChannelEventReceiver = broadcast::Receiver<Arc<ChannelDatum>>;
+pub use broadcast::error::RecvError as ChannelEventRecvError;
+
+/// ChannelDatum の追加や更新を WebSocket クライアント、 REST のロングポーリング、内部の購読者へプッシュ配信するバスです。
+/// 購読者は subscribe した時点以降に publish された ChannelDatum を受信できます。
+#[derive(Debug, Clone)]
+pub struct ChannelEventBus {
+ sender: broadcast::Sender<Arc<ChannelDatum>>,
+}
+
+impl ChannelEventBus {
+ pub fn new(capacity: usize) -> Self {
+  let (sender, _) = broadcast::channel(capacity.max(1));
+  Self { sender }
+ }
+
+ /// 追加または更新された ChannelDatum を購読者群へ配信します。
+ /// 購読者が居ない場合は何もしません。
+ pub fn publish(&self, cd: &ChannelDatum) {
+  if self.sender.receiver_count() == 0 {
+   return;
+  }
+  if let Err(e) = self.sender.send(Arc::new(cd.clone())) {
+   log::trace!("ChannelEventBus の購読者が居なくなったため配信は破棄されました: {:?}", e.0.get_id());
+  }
+ }
+
+ pub fn subscribe(&self) -> ChannelEventReceiver {
+  self.sender.subscribe()
+ }
+
+ pub fn subscriber_count(&self) -> usize {
+  self.sender.receiver_count()
+ }
+}
+
+impl Default for ChannelEventBus {
+ fn default() -> Self {
+  Self::new(DEFAULT_CHANNEL_EVENT_BUS_CAPACITY)
+ }
+}
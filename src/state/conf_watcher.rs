@@ -0,0 +1,51 @@
+use super::{SharedState, State};
+use crate::Conf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+
+pub const DEFAULT_CONF_HOT_RELOAD_INTERVAL_IN_MS: u64 = 1000;
+
+/// 設定ファイルの更新日時を定期的に確認し、変更されていれば再読み込みして State へ適用します。
+/// 読み込みや検証に失敗した場合は以前の設定を維持し、 system_channel へ失敗を通知します。
+pub fn spawn_conf_watcher(state: SharedState, path: PathBuf, interval: Duration) -> tokio::task::JoinHandle<()> {
+ tokio::spawn(async move {
+  log::info!("設定ファイル {:?} の変更の監視を開始します。", path);
+  let mut last_modified = get_modified(&path).await;
+  let mut interval = tokio::time::interval(interval);
+  interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+  loop {
+   interval.tick().await;
+
+   let modified = get_modified(&path).await;
+   if modified.is_none() || modified == last_modified {
+    continue;
+   }
+   last_modified = modified;
+
+   log::info!("設定ファイル {:?} の変更を検出したため再読み込みします。", path);
+   let message = match Conf::reload(path.clone()) {
+    Ok(conf) => match State::apply_conf(&state, conf).await {
+     Ok(count) => {
+      log::info!("設定ファイルを再読み込みし、 Processor {} 個を作り直しました。", count);
+      format!("設定ファイルを再読み込みし、 Processor {} 個を作り直しました。", count)
+     },
+     Err(e) => {
+      log::error!("設定ファイルの適用に失敗したため以前の設定を維持します: {:?}", e);
+      format!("設定ファイルの適用に失敗したため以前の設定を維持します: {}", e)
+     },
+    },
+    Err(e) => {
+     log::error!("設定ファイルの読み込みに失敗したため以前の設定を維持します: {:?}", e);
+     format!("設定ファイルの読み込みに失敗したため以前の設定を維持します: {}", e)
+    },
+   };
+   state.read().await.push_system_message(message).await;
+  }
+ })
+}
+
+async fn get_modified(path: &Path) -> Option<SystemTime> {
+ tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
@@ -0,0 +1,188 @@
+use super::{ChannelData, ChannelDatum, ChannelEventReceiver, ChannelEventRecvError, SharedChannelData};
+use crate::{Arc, RwLock};
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+pub const DEFAULT_STATE_DATA_JOURNAL_COMPACTION_THRESHOLD: usize = 1024;
+
+/// state_data_path の保存形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateDataFormat {
+ /// 全体をまとめて書き出す RON
+ Ron,
+ /// 1行に1件の ChannelDatum を追記していく JSON Lines
+ Journal,
+}
+
+impl StateDataFormat {
+ /// state_data_format の設定値、未設定の場合は state_data_path の拡張子から保存形式を決定します。
+ pub fn new(state_data_format: Option<&str>, state_data_path: Option<&Path>) -> Self {
+  let format = match state_data_format {
+   Some(format) => format.to_lowercase(),
+   None => state_data_path
+    .and_then(|path| path.extension())
+    .map(|extension| extension.to_string_lossy().to_lowercase())
+    .unwrap_or_default(),
+  };
+  match format.as_str() {
+   "jsonl" | "journal" => Self::Journal,
+   _ => Self::Ron,
+  }
+ }
+}
+
+/// JSON Lines のジャーナルから ChannelData を復元します。
+/// クラッシュなどで途中までしか書き込まれなかった行は警告を表示して読み飛ばします。
+/// 同じ ID の ChannelDatum が複数行ある場合は後から書かれた行で更新されます。
+pub async fn load<P: AsRef<Path>>(path: P) -> Result<SharedChannelData> {
+ let path = path.as_ref();
+ if !path.exists() {
+  log::warn!(
+   "指定されたファイルが存在しないため、読み込み処理を行えませんでした。 path = {:?}",
+   path
+  );
+  return Ok(Arc::new(RwLock::new(VecDeque::new())));
+ }
+
+ let journal = tokio::fs::read_to_string(path).await?;
+ let lines = journal.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>();
+
+ let mut channel_data = ChannelData::new();
+ let mut indices = HashMap::new();
+ let mut last_id = 0;
+ for (index, line) in lines.iter().enumerate() {
+  let cd = match serde_json::from_str::<ChannelDatum>(line) {
+   Ok(cd) => cd,
+   Err(e) if index + 1 == lines.len() => {
+    log::warn!(
+     "ジャーナルの最後の行が途中までしか書き込まれていないため読み飛ばします。 path = {:?} error = {:?}",
+     path,
+     e
+    );
+    continue;
+   },
+   Err(e) => {
+    log::warn!(
+     "ジャーナルの {} 行目を読み込めなかったため読み飛ばします。 path = {:?} error = {:?}",
+     index + 1,
+     path,
+     e
+    );
+    continue;
+   },
+  };
+  last_id = last_id.max(cd.get_id());
+  match indices.get(&cd.get_id()) {
+   Some(&i) => channel_data[i] = cd,
+   None => {
+    indices.insert(cd.get_id(), channel_data.len());
+    channel_data.push_back(cd);
+   },
+  }
+ }
+ ChannelDatum::advance_id_counter(last_id);
+
+ log::info!(
+  "ジャーナルから {} 件の ChannelDatum を復元しました。 path = {:?}",
+  channel_data.len(),
+  path
+ );
+
+ Ok(Arc::new(RwLock::new(channel_data)))
+}
+
+/// 現在の ChannelData だけでジャーナルを書き直します。
+/// 一時ファイルへ書き出してから置き換えるため、途中でクラッシュしても元のジャーナルは残ります。
+pub async fn compact<P: AsRef<Path>>(path: P, channel_data: &SharedChannelData) -> Result<()> {
+ compact_with(path.as_ref(), channel_data, None).await
+}
+
+/// compact と同じですが、 received が ChannelData に含まれていなければ末尾に追加して書き直します。
+/// 受信した ChannelDatum で圧縮する場合に、その ChannelDatum をジャーナルから取りこぼさないために使います。
+async fn compact_with(path: &Path, channel_data: &SharedChannelData, received: Option<&ChannelDatum>) -> Result<()> {
+ let mut journal = String::new();
+ {
+  let channel_data = channel_data.read().await;
+  for cd in channel_data.iter() {
+   journal.push_str(&serde_json::to_string(cd)?);
+   journal.push('\n');
+  }
+  if let Some(received) = received {
+   if !channel_data.iter().any(|cd| cd.get_id() == received.get_id()) {
+    journal.push_str(&serde_json::to_string(received)?);
+    journal.push('\n');
+   }
+  }
+ }
+
+ let temp_path = temp_path(path);
+ {
+  let mut file = tokio::fs::File::create(&temp_path).await?;
+  file.write_all(journal.as_bytes()).await?;
+  file.sync_all().await?;
+ }
+ tokio::fs::rename(&temp_path, path).await?;
+ log::debug!("ジャーナルを圧縮しました。 path = {:?}", path);
+ Ok(())
+}
+
+/// ChannelEventBus を購読し、追加または更新された ChannelDatum をジャーナルへ1行ずつ追記するタスクを開始します。
+/// 追記が compaction_threshold 行に達するか、購読が追いつかずに取りこぼした場合はジャーナルを圧縮します。
+pub fn spawn_writer(path: PathBuf, channel_data: SharedChannelData, mut receiver: ChannelEventReceiver, compaction_threshold: usize) {
+ tokio::spawn(async move {
+  let mut appended = 0;
+  let mut file = None;
+
+  loop {
+   let mut received = None;
+   let needs_compaction = match receiver.recv().await {
+    Ok(cd) => {
+     if let Err(e) = append(&path, &mut file, &cd).await {
+      log::error!("ジャーナルへの追記に失敗しました。 path = {:?} error = {:?}", path, e);
+      file = None;
+     }
+     appended += 1;
+     received = Some(cd);
+     appended >= compaction_threshold
+    },
+    Err(ChannelEventRecvError::Lagged(n)) => {
+     log::warn!(
+      "ジャーナルへの追記が追いつかず {} 件を取りこぼしたため、現在の内容でジャーナルを書き直します。",
+      n
+     );
+     true
+    },
+    Err(ChannelEventRecvError::Closed) => break,
+   };
+
+   if needs_compaction {
+    // 追記用のファイルを閉じてから書き直す
+    file = None;
+    match compact_with(&path, &channel_data, received.as_deref()).await {
+     Ok(()) => appended = 0,
+     Err(e) => log::error!("ジャーナルの圧縮に失敗しました。 path = {:?} error = {:?}", path, e),
+    }
+   }
+  }
+ });
+}
+
+async fn append(path: &Path, file: &mut Option<tokio::fs::File>, cd: &ChannelDatum) -> Result<()> {
+ if file.is_none() {
+  *file = Some(tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?);
+ }
+ let mut line = serde_json::to_string(cd)?;
+ line.push('\n');
+ let file = file.as_mut().unwrap();
+ file.write_all(line.as_bytes()).await?;
+ file.flush().await?;
+ Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+ let mut temp_path = path.as_os_str().to_owned();
+ temp_path.push(".tmp");
+ PathBuf::from(temp_path)
+}
@@ -1,47 +1,114 @@
 mod channel_datum;
+mod channel_event_bus;
+mod conf_watcher;
+mod journal;
+mod replay;
+mod retention;
 
-pub use channel_datum::{ChannelData, ChannelDatum, SharedChannelData};
+pub use channel_datum::{ChannelData, ChannelDatum, ChannelDatumHop, ChannelDatumMetadata, SharedChannelData};
+pub use channel_event_bus::{ChannelEventBus, ChannelEventReceiver, ChannelEventRecvError};
+pub use conf_watcher::{spawn_conf_watcher, DEFAULT_CONF_HOT_RELOAD_INTERVAL_IN_MS};
+pub use journal::StateDataFormat;
+pub use replay::replay;
+pub use retention::RetentionPolicy;
 
-use crate::{processor::*, Arc, Conf, RwLock, SharedAudioSink};
+use crate::{processor::*, Arc, AudioCache, AudioClips, AudioQueue, AudioQueues, Conf, RwLock, SharedAudioSink};
 use anyhow::Result;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 
 pub type SharedState = Arc<RwLock<State>>;
 
 const DEFAULT_STATE_DATA_CAPACITY: usize = 256;
+const DEFAULT_PROCESSOR_HOP_LIMIT: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct State {
  pub state_data_capacity: usize,
+ pub retention: RetentionPolicy,
  pub state_data_path: Option<PathBuf>,
  pub state_data_auto_save: bool,
  pub state_data_pretty: bool,
+ pub state_data_format: StateDataFormat,
+ pub processor_hop_limit: usize,
  pub channel_data: SharedChannelData,
- pub processors: Vec<ProcessorKind>,
+ pub channel_event_bus: ChannelEventBus,
+ pub processors: Vec<SharedProcessor>,
+ pub processor_registry: ProcessorRegistry,
+ /// 現在の Processor 群の元になった設定。設定の再読み込み時の差分の検出に使います。
+ pub conf: Conf,
  pub audio_sink: SharedAudioSink,
+ /// 音声合成系の Processor 群が共有する、既定の出力先と音声出力デバイスごとの再生キュー群
+ pub audio_queues: AudioQueues,
+ /// ブラウザーなどへ配信するために一時的に保持している合成した音声
+ pub audio_clips: AudioClips,
+ /// audio_cache_path が設定されている場合の合成した音声のキャッシュ
+ pub audio_cache: Option<AudioCache>,
 }
 
 impl State {
- pub async fn new(conf: &Conf, audio_sink: SharedAudioSink) -> Result<SharedState> {
-  let channel_data = match &conf.state_data_path {
-   Some(path) => load_channel_data(path).await?,
-   None => Arc::new(RwLock::new(VecDeque::new())),
+ pub async fn new(conf: &Conf, audio_sink: SharedAudioSink, processor_registry: ProcessorRegistry) -> Result<SharedState> {
+  let state_data_capacity = conf.state_data_capacity.unwrap_or(DEFAULT_STATE_DATA_CAPACITY);
+  let state_data_format = StateDataFormat::new(conf.state_data_format.as_deref(), conf.state_data_path.as_deref());
+  let retention = RetentionPolicy::new(conf, state_data_capacity);
+  let channel_data = match (&conf.state_data_path, state_data_format) {
+   (Some(path), StateDataFormat::Ron) => load_channel_data(path).await?,
+   (Some(path), StateDataFormat::Journal) => journal::load(path).await?,
+   (None, _) => Arc::new(RwLock::new(VecDeque::new())),
   };
+  retention.enforce(&mut *channel_data.write().await);
+  if let (Some(path), StateDataFormat::Journal) = (&conf.state_data_path, state_data_format) {
+   // 途中までしか書き込まれていない行の後ろへ追記しないよう、読み込んだ内容で書き直しておく
+   journal::compact(path, &channel_data).await?;
+  }
   log::trace!("ChannelData の初期化が完了しました。");
 
+  let channel_event_bus = ChannelEventBus::new(
+   conf
+    .channel_event_bus_capacity
+    .unwrap_or(channel_event_bus::DEFAULT_CHANNEL_EVENT_BUS_CAPACITY),
+  );
+
+  // ジャーナル形式で自動保存する場合は ChannelEventBus を購読して追記
+  if let (Some(path), StateDataFormat::Journal, Some(true)) = (&conf.state_data_path, state_data_format, conf.state_data_auto_save) {
+   journal::spawn_writer(
+    path.clone(),
+    channel_data.clone(),
+    channel_event_bus.subscribe(),
+    conf
+     .state_data_journal_compaction_threshold
+     .unwrap_or(journal::DEFAULT_STATE_DATA_JOURNAL_COMPACTION_THRESHOLD),
+   );
+  }
+
   let state = Arc::new(RwLock::new(Self {
-   state_data_capacity: conf.state_data_capacity.unwrap_or(DEFAULT_STATE_DATA_CAPACITY),
+   state_data_capacity,
+   retention,
    state_data_path: conf.state_data_path.clone(),
    state_data_auto_save: conf.state_data_auto_save.unwrap_or(false),
    state_data_pretty: conf.state_data_pretty.unwrap_or(false),
+   state_data_format,
+   processor_hop_limit: conf.processor_hop_limit.unwrap_or(DEFAULT_PROCESSOR_HOP_LIMIT),
    channel_data,
+   channel_event_bus,
    processors: vec![],
+   processor_registry: processor_registry.clone(),
+   conf: conf.clone(),
+   audio_queues: AudioQueues::new(AudioQueue::spawn(audio_sink.clone(), None)),
    audio_sink,
+   audio_clips: AudioClips::new(conf.audio_clip_ttl_in_secs.unwrap_or(crate::audio::DEFAULT_AUDIO_CLIP_TTL_IN_SECS)),
+   audio_cache: conf.audio_cache_path.as_ref().map(|path| {
+    AudioCache::new(
+     path,
+     conf
+      .audio_cache_max_size_in_mb
+      .unwrap_or(crate::audio::DEFAULT_AUDIO_CACHE_MAX_SIZE_IN_MB),
+    )
+   }),
   }));
   log::trace!("State の生成が完了しました。");
 
-  let processors = match init_processors(&conf, &state).await {
+  let processors = match init_processors(&conf, &processor_registry, &state).await {
    Ok(processors) => processors,
    Err(e) => {
     log::error!("Processor の初期化に失敗しました。直前に表示されたエラーログ等を参考に設定の見直しを検討して下さい。🙏");
@@ -61,6 +128,11 @@ impl State {
   datum.cloned()
  }
 
+ /// ChannelDatum の追加/更新の購読を開始します。
+ pub fn subscribe(&self) -> ChannelEventReceiver {
+  self.channel_event_bus.subscribe()
+ }
+
  pub async fn push_channel_data(&self, channel_data: ChannelData) {
   for cd in channel_data.into_iter() {
    self.push_channel_datum(cd).await;
@@ -71,22 +143,54 @@ impl State {
   let id = cd.get_id();
   let channel_from = cd.channel.clone();
 
+  // ループの検出
+  if cd.get_hops() > self.processor_hop_limit {
+   log::error!(
+    "ChannelDatum が {} 段の Processor を経由し processor_hop_limit = {} を超えたため破棄します。設定の channel_from/channel_to にループが無いか確認して下さい。🙏 channel = {:?} provenance = {:?}",
+    cd.get_hops(),
+    self.processor_hop_limit,
+    channel_from,
+    cd.get_provenance()
+   );
+   return;
+  }
+  for p in self.processors.iter() {
+   if cd.get_hops() > 0 && p.is_enabled().await && p.is_channel_from(&channel_from).await {
+    let processor_id = p.get_id().await;
+    if cd.has_visited(&processor_id) {
+     log::error!(
+      "ChannelDatum が既に経由した Processor {:?} へ再び入力されようとしたためループとして破棄します。設定の channel_from/channel_to にループが無いか確認して下さい。🙏 channel = {:?} provenance = {:?}",
+      processor_id,
+      channel_from,
+      cd.get_provenance()
+     );
+     return;
+    }
+   }
+  }
+
   // データ追加
   log::trace!("ChannelDatum を追加します: {:?}", cd);
   {
    let mut channel_data = self.channel_data.write().await;
-   channel_data.push_back(cd);
-   if channel_data.len() > self.state_data_capacity {
-    log::trace!("channel_data の容量が上限を超えたため、先頭の要素を削除します。");
-    channel_data.pop_front();
+   channel_data.push_back(cd.clone());
+   let removed = self.retention.enforce(&mut channel_data);
+   if removed > 0 {
+    log::trace!("channel_data の保持の規則に従って {} 件の要素を削除しました。", removed);
    }
    log::trace!("channel_data の容量: {}", channel_data.len());
   }
+  // 購読者が channel_data から読み直しても見つかるよう、追加と保持の規則の適用が終わってから配信
+  self.channel_event_bus.publish(&cd);
 
   // Processor の実行
   for (i, p) in self.processors.iter().enumerate() {
    log::trace!("Processor を実行します: {:?} / {:?}", i + 1, self.processors.len());
    if p.is_channel_from(&channel_from).await {
+    if !p.is_enabled().await {
+     log::trace!("Processor は無効化されているためスキップします。");
+     continue;
+    }
     match p.process(id).await {
      Ok(ca) => {
       log::trace!("Processor の実行が完了しました。(非同期処理部分が継続して実行中の可能性があります。)");
@@ -104,7 +208,8 @@ impl State {
   }
   log::trace!("Processors の実行が完了しました。");
 
-  if self.state_data_auto_save {
+  // ジャーナル形式の場合は ChannelEventBus を購読しているタスクが追記するため全体の保存は行わない
+  if self.state_data_auto_save && self.state_data_format == StateDataFormat::Ron {
    log::trace!("state_data_auto_save が有効になっているため、保存処理を行います。");
    self.save().await.unwrap();
   }
@@ -117,18 +222,175 @@ impl State {
    return Ok(());
   }
   let path = self.state_data_path.as_ref().unwrap();
-  save_channel_data(path, &self.channel_data).await?;
+  match self.state_data_format {
+   StateDataFormat::Ron => save_channel_data(path, &self.channel_data).await?,
+   StateDataFormat::Journal => journal::compact(path, &self.channel_data).await?,
+  }
   Ok(())
  }
 
  pub async fn load(&mut self) -> Result<()> {
-  let channel_data = load_channel_data(self.state_data_path.as_ref().unwrap()).await?;
-  self.channel_data = channel_data;
+  let path = self.state_data_path.as_ref().unwrap();
+  let channel_data = match self.state_data_format {
+   StateDataFormat::Ron => load_channel_data(path).await?,
+   StateDataFormat::Journal => journal::load(path).await?,
+  };
+  // Processor やジャーナルの追記タスクが保持している SharedChannelData もそのまま使えるよう中身を入れ替える
+  let mut channel_data = std::mem::take(&mut *channel_data.write().await);
+  self.retention.enforce(&mut channel_data);
+  *self.channel_data.write().await = channel_data;
   Ok(())
  }
+
+ /// group に属する Processor の有効/無効を切り替え、対象になった Processor の数を返します。
+ pub async fn set_processor_group_enabled(&self, group: &str, is_enabled: bool) -> usize {
+  let mut count = 0;
+  for p in self.processors.iter() {
+   let conf = p.conf();
+   let mut conf = conf.write().await;
+   if conf.is_in_group(group) {
+    log::info!(
+     "Processor {:?} を{}します。",
+     conf.get_id(),
+     if is_enabled { "有効化" } else { "無効化" }
+    );
+    conf.is_enabled = is_enabled;
+    count += 1;
+   }
+  }
+  count
+ }
+
+ /// group に属する Processor の設定を設定ファイルから再読み込みして作り直し、作り直した Processor の数を返します。
+ /// Processor の生成中に State を読むため、 push_channel_datum の処理中に呼ぶ場合は tokio::spawn などで切り離して下さい。
+ pub async fn reload_processor_group(state: &SharedState, group: &str) -> Result<usize> {
+  let conf_path = state.read().await.conf.conf_path.clone();
+  let conf_path = match conf_path {
+   Some(conf_path) => conf_path,
+   None => anyhow::bail!("設定ファイルのパスが不明なため再読み込みできません。"),
+  };
+  let conf = Conf::reload(conf_path)?;
+
+  // 再読み込み前または後に group に属する Processor が作り直しの対象
+  let mut targets = conf
+   .processors
+   .iter()
+   .filter(|pc| pc.is_in_group(group))
+   .map(|pc| pc.get_id())
+   .collect::<HashSet<_>>();
+  for p in state.read().await.processors.iter() {
+   if p.conf().read().await.is_in_group(group) {
+    targets.insert(p.get_id().await);
+   }
+  }
+
+  // 設定ファイルから無くなった group 外の Processor はそのまま維持
+  let reloaded = rebuild_processors(state, &conf, &targets, true).await?;
+
+  // 作り直した Processor の設定だけを差分検出用の設定へ反映
+  let mut state = state.write().await;
+  state.conf.processors.retain(|pc| !targets.contains(&pc.get_id()));
+  state
+   .conf
+   .processors
+   .extend(conf.processors.into_iter().filter(|pc| targets.contains(&pc.get_id())));
+
+  Ok(reloaded)
+ }
+
+ /// 新しい設定を適用します。 ProcessorConf が変化した Processor だけを作り直し、チャンネルのデータや WebSocket の接続は維持されます。
+ /// 作り直した Processor の数を返します。 Processor の生成に失敗した場合は以前の Processor 群が維持されます。
+ pub async fn apply_conf(state: &SharedState, conf: Conf) -> Result<usize> {
+  let targets = {
+   let state = state.read().await;
+   conf
+    .processors
+    .iter()
+    .filter(|pc| {
+     let id = pc.get_id();
+     state.conf.processors.iter().find(|old| old.get_id() == id) != Some(pc)
+    })
+    .map(|pc| pc.get_id())
+    .collect::<HashSet<_>>()
+  };
+
+  // 設定ファイルから無くなった Processor は破棄
+  let rebuilt = rebuild_processors(state, &conf, &targets, false).await?;
+
+  let mut state = state.write().await;
+  if state.conf.web_ui_address != conf.web_ui_address
+   || state.conf.workers != conf.workers
+   || state.conf.web_ui_resources_path != conf.web_ui_resources_path
+   || state.conf.twitch.is_some() != conf.twitch.is_some()
+   || state.conf.state_data_path != conf.state_data_path
+   || state.conf.state_data_format != conf.state_data_format
+   || state.conf.state_data_auto_save != conf.state_data_auto_save
+   || state.conf.audio_backend != conf.audio_backend
+   || state.conf.audio_wav_path != conf.audio_wav_path
+   || state.conf.audio_wav_file_max_secs != conf.audio_wav_file_max_secs
+   || state.conf.audio_clip_ttl_in_secs != conf.audio_clip_ttl_in_secs
+   || state.conf.audio_cache_path != conf.audio_cache_path
+   || state.conf.audio_cache_max_size_in_mb != conf.audio_cache_max_size_in_mb
+  {
+   log::warn!("web_ui_address, workers, web_ui_resources_path, twitch, state_data_path, state_data_format, state_data_auto_save, audio_backend, audio_wav_path, audio_wav_file_max_secs, audio_clip_ttl_in_secs, audio_cache_path, audio_cache_max_size_in_mb の変更を反映するには VAC の再起動が必要です。");
+  }
+  state.state_data_capacity = conf.state_data_capacity.unwrap_or(DEFAULT_STATE_DATA_CAPACITY);
+  state.retention = RetentionPolicy::new(&conf, state.state_data_capacity);
+  state.state_data_pretty = conf.state_data_pretty.unwrap_or(false);
+  state.processor_hop_limit = conf.processor_hop_limit.unwrap_or(DEFAULT_PROCESSOR_HOP_LIMIT);
+  state.conf = conf;
+
+  Ok(rebuilt)
+ }
+
+ /// システムからの通知を system_channel へ送信します。
+ pub async fn push_system_message(&self, content: String) {
+  let cd = ChannelDatum::new(self.conf.get_system_channel().to_string(), content).with_flag(ChannelDatum::FLAG_IS_FINAL);
+  self.push_channel_datum(cd).await;
+ }
+}
+
+/// conf.processors の定義順に Processor 群を組み直し、作り直した Processor の数を返します。
+/// targets に ID が含まれる Processor は作り直し、それ以外は同じ ID の既存の Processor を維持します。
+/// keep_missing が true の場合は conf.processors に無い既存の Processor も targets に含まれなければ維持します。
+async fn rebuild_processors(state: &SharedState, conf: &Conf, targets: &HashSet<String>, keep_missing: bool) -> Result<usize> {
+ let registry = state.read().await.processor_registry.clone();
+
+ let mut current = vec![];
+ for p in state.read().await.processors.iter() {
+  current.push((p.get_id().await, p.clone()));
+ }
+
+ let mut processors = vec![];
+ let mut rebuilt = 0;
+ for pc in conf.processors.iter() {
+  let id = pc.get_id();
+  if targets.contains(&id) {
+   log::info!("Processor {:?} を新しい設定で作り直します。", id);
+   match registry.create(pc, state).await {
+    Some(p) => {
+     processors.push(p?);
+     rebuilt += 1;
+    },
+    None => log::warn!("未実装の ProcessorConf が指定されました: {:?}", pc),
+   }
+  } else if let Some(index) = current.iter().position(|(current_id, _)| current_id == &id) {
+   processors.push(current.remove(index).1);
+  }
+ }
+ for (id, p) in current {
+  if keep_missing && !targets.contains(&id) {
+   processors.push(p);
+  } else {
+   log::info!("Processor {:?} は設定から無くなったため破棄します。", id);
+  }
+ }
+
+ state.write().await.processors = processors;
+ Ok(rebuilt)
 }
 
-async fn init_processors(conf: &Conf, state: &SharedState) -> Result<Vec<ProcessorKind>> {
+async fn init_processors(conf: &Conf, registry: &ProcessorRegistry, state: &SharedState) -> Result<Vec<SharedProcessor>> {
  let mut processors = Vec::new();
 
  for pc in conf.processors.iter() {
@@ -137,22 +399,18 @@ async fn init_processors(conf: &Conf, state: &SharedState) -> Result<Vec<Process
   }
   let feature = pc.feature.as_ref().unwrap();
   log::info!("Processor を初期化します: {:?}", feature);
-  let pk = match feature.to_lowercase().as_str() {
-   Command::FEATURE => Command::new(&pc, state).await?,
-   Modify::FEATURE => Modify::new(&pc, state).await?,
-   Screenshot::FEATURE => Screenshot::new(&pc, state).await?,
-   Ocr::FEATURE => Ocr::new(&pc, state).await?,
-   OpenAiChat::FEATURE => OpenAiChat::new(&pc, state).await?,
-   GasTranslation::FEATURE => GasTranslation::new(&pc, state).await?,
-   Bouyomichan::FEATURE => Bouyomichan::new(&pc, state).await?,
-   CoeiroInk::FEATURE => CoeiroInk::new(&pc, state).await?,
-   OsTts::FEATURE => OsTts::new(&pc, state).await?,
-   _ => {
-    log::warn!("未実装の ProcessorConf が指定されました: {:?}", pc);
+  let p = match registry.create(pc, state).await {
+   Some(p) => p?,
+   None => {
+    log::warn!(
+     "未実装の ProcessorConf が指定されました。利用可能な feature = {:?} ProcessorConf = {:?}",
+     registry.features(),
+     pc
+    );
     continue;
    },
   };
-  processors.push(pk);
+  processors.push(p);
  }
 
  Ok(processors)
@@ -179,3 +437,41 @@ async fn save_channel_data<P: AsRef<Path>>(path: P, channel_data: &SharedChannel
  tokio::fs::write(path, serialized_channel_data).await?;
  Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::{AudioBackend, AudioOutput};
+ use std::time::Duration;
+
+ #[tokio::test]
+ async fn published_datum_is_already_in_channel_data() {
+  let conf: Conf = toml::from_str("").unwrap();
+  let audio_output = AudioOutput::open(&AudioBackend::Null).unwrap();
+  let state = State::new(&conf, audio_output.audio_sink.clone(), ProcessorRegistry::new())
+   .await
+   .unwrap();
+  let channel_data = state.read().await.channel_data.clone();
+  let mut receiver = state.read().await.subscribe();
+
+  let cd = ChannelDatum::new("test".to_string(), "hello".to_string());
+  let id = cd.get_id();
+
+  // channel_data へ書き込めない間は配信されない
+  let guard = channel_data.read().await;
+  let push = {
+   let state = state.clone();
+   tokio::spawn(async move { state.read().await.push_channel_datum(cd).await })
+  };
+  assert!(tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await.is_err());
+  drop(guard);
+
+  // 配信を受けた時点で channel_data から読み直せる
+  let published = receiver.recv().await.unwrap();
+  assert_eq!(published.get_id(), id);
+  let stored = state.read().await.rfind_channel_datum(id).await;
+  assert_eq!(stored.map(|cd| cd.content), Some("hello".to_string()));
+
+  push.await.unwrap();
+ }
+}
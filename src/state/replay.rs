@@ -0,0 +1,70 @@
+use super::{journal, load_channel_data, ChannelDatum, ChannelDatumMetadata, SharedState, StateDataFormat};
+use anyhow::{bail, Result};
+use std::path::Path;
+use tokio::time::{Duration, Instant};
+
+/// 保存された channel_data (RON またはジャーナル) を読み込み、元の時間間隔を speed 倍速にして push_channel_datum で再入力します。
+/// channels が空の場合は Processor を経由せずに入力された ChannelDatum だけを再入力します。
+/// speed が 0 以下の場合は待たずに次々と再入力します。
+pub async fn replay<P: AsRef<Path>>(state: SharedState, path: P, channels: &[String], speed: f64) -> Result<()> {
+ let path = path.as_ref();
+ if !path.exists() {
+  bail!("再生するファイルが見つかりませんでした: {:?}", path);
+ }
+
+ // 読み込みは ID の採番を進めるだけなので、既にある ChannelDatum と ID が重複することはない
+ let recorded = match StateDataFormat::new(None, Some(path)) {
+  StateDataFormat::Ron => load_channel_data(path).await?,
+  StateDataFormat::Journal => journal::load(path).await?,
+ };
+
+ let mut recorded = recorded
+  .read()
+  .await
+  .iter()
+  .filter(|cd| match channels.is_empty() {
+   true => cd.get_hops() == 0,
+   false => channels.contains(&cd.channel),
+  })
+  .cloned()
+  .collect::<Vec<_>>();
+ recorded.sort_by_key(|cd| cd.get_datetime());
+
+ let first_datetime = match recorded.first() {
+  Some(cd) => cd.get_datetime(),
+  None => {
+   log::warn!("再生する ChannelDatum がありませんでした: {:?}", path);
+   return Ok(());
+  },
+ };
+ log::info!(
+  "{:?} から {} 件の ChannelDatum を {} 倍速で再生します。",
+  path,
+  recorded.len(),
+  speed
+ );
+
+ let started = Instant::now();
+ for (index, cd) in recorded.into_iter().enumerate() {
+  if speed > 0.0 {
+   let elapsed = (cd.get_datetime() - first_datetime).to_std().unwrap_or_default();
+   tokio::time::sleep_until(started + Duration::from_secs_f64(elapsed.as_secs_f64() / speed)).await;
+  }
+
+  log::debug!("再生 {}: channel = {:?} content = {:?}", index + 1, cd.channel, cd.content);
+  // 来歴は再生した時点の処理で新たに作られるため、発言者や言語などだけを引き継ぐ
+  let metadata = ChannelDatumMetadata {
+   source_id: None,
+   source_channel: None,
+   processor: None,
+   audio_url: None,
+   ..cd.metadata
+  };
+  let mut replayed = ChannelDatum::new(cd.channel, cd.content).with_metadata(metadata);
+  replayed.flags = cd.flags;
+  state.read().await.push_channel_datum(replayed).await;
+ }
+
+ log::info!("{:?} の再生が完了しました。", path);
+ Ok(())
+}
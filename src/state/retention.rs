@@ -0,0 +1,142 @@
+use super::{ChannelData, ChannelDatum};
+use crate::Conf;
+use chrono::{Duration, Utc};
+use std::collections::{HashMap, HashSet};
+
+/// channel_data に保持する ChannelDatum を決める規則です。
+/// 次の順に適用されます:
+///  1. チャンネルごとの keep_latest_final 件の確定済みの ChannelDatum は ttl や全体の上限では削除しない
+///  2. ttl_in_secs を過ぎた ChannelDatum を削除 (ピン留めされたチャンネルを除く)
+///  3. チャンネルごとの capacity を超えた古い ChannelDatum を削除
+///  4. state_data_capacity を超えた古い ChannelDatum を削除 (ピン留めされたチャンネルを除く)
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+ capacity: usize,
+ ttl: Option<Duration>,
+ pinned_channels: HashSet<String>,
+ channels: HashMap<String, ChannelRetention>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChannelRetention {
+ capacity: Option<usize>,
+ ttl: Option<Duration>,
+ keep_latest_final: usize,
+}
+
+impl RetentionPolicy {
+ pub fn new(conf: &Conf, capacity: usize) -> Self {
+  let mut pinned_channels = conf.state_data_pinned_channels.iter().cloned().collect::<HashSet<_>>();
+  let mut channels = HashMap::new();
+  for c in conf.state_data_channels.iter() {
+   if c.pinned.unwrap_or_default() {
+    pinned_channels.insert(c.channel.clone());
+   }
+   channels.insert(
+    c.channel.clone(),
+    ChannelRetention {
+     capacity: c.capacity,
+     ttl: c.ttl_in_secs.map(to_duration),
+     keep_latest_final: c.keep_latest_final.unwrap_or_default(),
+    },
+   );
+  }
+
+  Self {
+   capacity,
+   ttl: conf.state_data_ttl_in_secs.map(to_duration),
+   pinned_channels,
+   channels,
+  }
+ }
+
+ pub fn is_pinned(&self, channel: &str) -> bool {
+  self.pinned_channels.contains(channel)
+ }
+
+ /// 規則に従って channel_data から ChannelDatum を削除し、削除した件数を返します。
+ pub fn enforce(&self, channel_data: &mut ChannelData) -> usize {
+  let now = Utc::now();
+  let mut removes = vec![false; channel_data.len()];
+  let mut protects = vec![false; channel_data.len()];
+
+  // チャンネルごとの位置を古い順に集める
+  let mut indices_by_channel = HashMap::<&str, Vec<usize>>::new();
+  for (index, cd) in channel_data.iter().enumerate() {
+   indices_by_channel.entry(cd.channel.as_str()).or_default().push(index);
+  }
+
+  for (channel, indices) in indices_by_channel.iter() {
+   let pinned = self.is_pinned(channel);
+   let retention = self.channels.get(*channel);
+
+   // 最新の keep_latest_final 件の確定済みの ChannelDatum を保護
+   let keep_latest_final = retention.map(|r| r.keep_latest_final).unwrap_or_default();
+   for &index in indices
+    .iter()
+    .rev()
+    .filter(|&&index| channel_data[index].has_flag(ChannelDatum::FLAG_IS_FINAL))
+    .take(keep_latest_final)
+   {
+    protects[index] = true;
+   }
+
+   // ttl
+   if let Some(ttl) = retention.and_then(|r| r.ttl).or(self.ttl) {
+    if !pinned {
+     for &index in indices.iter() {
+      if !protects[index] && now - channel_data[index].get_datetime() > ttl {
+       removes[index] = true;
+      }
+     }
+    }
+   }
+
+   // チャンネルごとの上限
+   if let Some(capacity) = retention.and_then(|r| r.capacity) {
+    let mut remaining = indices.iter().filter(|&&index| !removes[index]).count();
+    for &index in indices.iter() {
+     if remaining <= capacity {
+      break;
+     }
+     if !removes[index] && !protects[index] {
+      removes[index] = true;
+      remaining -= 1;
+     }
+    }
+   }
+  }
+
+  // 全体の上限
+  let mut remaining = removes.iter().filter(|&&remove| !remove).count();
+  for (index, cd) in channel_data.iter().enumerate() {
+   if remaining <= self.capacity {
+    break;
+   }
+   if !removes[index] && !protects[index] && !self.is_pinned(&cd.channel) {
+    removes[index] = true;
+    remaining -= 1;
+   }
+  }
+  if remaining > self.capacity {
+   log::trace!(
+    "ピン留めまたは保護された ChannelDatum が多いため channel_data は state_data_capacity = {} を超えて {} 件を保持しています。",
+    self.capacity,
+    remaining
+   );
+  }
+
+  let mut index = 0;
+  let before = channel_data.len();
+  channel_data.retain(|_| {
+   let remove = removes[index];
+   index += 1;
+   !remove
+  });
+  before - channel_data.len()
+ }
+}
+
+fn to_duration(secs: u64) -> Duration {
+ Duration::seconds(secs as i64)
+}
@@ -0,0 +1,38 @@
+use crate::{resource::CONTENT_TYPE_APPLICATION_JSON, AudioCommand, Result, SharedState};
+use actix_web::{get, post, web, HttpResponse, Responder};
+
+/// 再生キューの状態を取得します。
+#[get("/audio/queue")]
+async fn get_queue(state: web::Data<SharedState>) -> Result<impl Responder> {
+ log::trace!("/audio/queue");
+ let audio_queues = state.get_ref().read().await.audio_queues.clone();
+ let status = audio_queues.status().await;
+ Ok(HttpResponse::Ok().content_type(CONTENT_TYPE_APPLICATION_JSON).json(status))
+}
+
+/// 合成した音声を取得します。 ChannelDatum の metadata の audio_url から参照されます。
+/// VAC とは別の PC のブラウザーからも取得できるよう Access-Control-Allow-Origin を付けます。
+#[get("/audio/{id:\\d+}")]
+async fn get_clip(state: web::Data<SharedState>, id: web::Path<u64>) -> Result<impl Responder> {
+ log::trace!("/audio/{}", id);
+ let audio_clips = state.get_ref().read().await.audio_clips.clone();
+ match audio_clips.get(id.into_inner()).await {
+  Some((mime, data)) => Ok(
+   HttpResponse::Ok()
+    .content_type(mime)
+    .insert_header(("Access-Control-Allow-Origin", "*"))
+    .body(data.as_ref().clone()),
+  ),
+  None => Ok(HttpResponse::NotFound().finish()),
+ }
+}
+
+/// 再生キューを操作し、操作後の状態を返します。
+/// body の例: {"command":"skip"} {"command":"skip","id":3} {"command":"stop"} {"command":"clear-audio"} {"command":"volume","volume":50}
+#[post("/audio")]
+async fn post(state: web::Data<SharedState>, command: web::Json<AudioCommand>) -> Result<impl Responder> {
+ log::trace!("/audio: {:?}", command);
+ let audio_queues = state.get_ref().read().await.audio_queues.clone();
+ let status = audio_queues.execute(&command).await;
+ Ok(HttpResponse::Ok().content_type(CONTENT_TYPE_APPLICATION_JSON).json(status))
+}
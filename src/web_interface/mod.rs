@@ -1,3 +1,4 @@
+pub mod audio;
 pub mod input;
 pub mod output;
 pub mod status;
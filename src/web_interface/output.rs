@@ -1,13 +1,18 @@
-use crate::{resource::CONTENT_TYPE_APPLICATION_JSON, Result, SharedState};
+use crate::{
+ resource::CONTENT_TYPE_APPLICATION_JSON, state::ChannelEventRecvError, ChannelData, ChannelDatumMetadata, Result, SharedState,
+};
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use tokio::time::Duration;
 
 #[derive(Deserialize, Debug)]
 struct OutputRequestPayload {
  /// 受信したいチャンネルのリスト
  channels: Vec<ChannelRequest>,
+ /// ロングポーリング用。該当する内容が1件も無い場合に、新たな内容が届くまで最大で指定したミリ秒だけ応答を待機します。
+ wait_in_ms: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -41,19 +46,51 @@ struct ChannelDatum {
  /// フラグ
  #[serde(default)]
  flags: HashSet<String>,
+ /// 元になった ChannelDatum や言語などの付加情報
+ metadata: ChannelDatumMetadata,
 }
 
 #[post("/output")]
 async fn post(state: web::Data<SharedState>, request_paylaod: web::Json<OutputRequestPayload>) -> Result<impl Responder> {
+ // state から channel_data を取得する
+ // ロングポーリングで取りこぼしが無いよう、 channel_data を読む前に購読を開始しておく
+ let (channel_data, mut receiver) = {
+  let state = state.get_ref().read().await;
+  (state.channel_data.clone(), state.subscribe())
+ };
+
+ let mut output_response = make_output_response(&*channel_data.read().await, &request_paylaod);
+
+ // wait_in_ms が指定されていて該当する内容が無い場合は、要求されたチャンネルに内容が届くまで待機してから再取得する
+ if let Some(wait_in_ms) = request_paylaod.wait_in_ms {
+  if output_response.channel_data.values().all(|v| v.is_empty()) {
+   let wait_for_datum = async {
+    loop {
+     match receiver.recv().await {
+      Ok(cd) if request_paylaod.channels.iter().any(|cr| cr.name == cd.channel) => break,
+      Ok(_) | Err(ChannelEventRecvError::Lagged(_)) => continue,
+      Err(ChannelEventRecvError::Closed) => break,
+     }
+    }
+   };
+   if tokio::time::timeout(Duration::from_millis(wait_in_ms), wait_for_datum)
+    .await
+    .is_ok()
+   {
+    output_response = make_output_response(&*channel_data.read().await, &request_paylaod);
+   }
+  }
+ }
+
+ Ok(HttpResponse::Ok().content_type(CONTENT_TYPE_APPLICATION_JSON).json(output_response))
+}
+
+fn make_output_response(channel_data: &ChannelData, request_paylaod: &OutputRequestPayload) -> OutputResponsePayload {
  // 出力の構造を作成
  let mut output_response = OutputResponsePayload {
   channel_data: HashMap::new(),
  };
 
- // state から channel_data を取得する
- let state = state.get_ref().read().await;
- let channel_data = state.channel_data.read().await;
-
  // request_payload から channel_request を1つずつ取り出して、それぞれに対応する channel_data を取得する
  for channel_request in request_paylaod.channels.iter() {
   let mut channel_data_for_response = Vec::new();
@@ -96,6 +133,7 @@ async fn post(state: web::Data<SharedState>, request_paylaod: web::Json<OutputRe
     channel: Some(cd.channel.clone()),
     content: Some(cd.content.clone()),
     flags: cd.flags.clone(),
+    metadata: cd.metadata.clone(),
    });
   }
 
@@ -105,7 +143,7 @@ async fn post(state: web::Data<SharedState>, request_paylaod: web::Json<OutputRe
    .insert(channel_request.name.clone(), channel_data_for_response);
  }
 
- Ok(HttpResponse::Ok().content_type(CONTENT_TYPE_APPLICATION_JSON).json(output_response))
+ output_response
 }
 
 #[actix_web::get("/output")]
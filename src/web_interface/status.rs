@@ -1,5 +1,7 @@
-use crate::{resource::CONTENT_TYPE_TEXT_HTML, Result};
-use actix_web::{get, HttpResponse, Responder};
+use crate::{
+ resource::CONTENT_TYPE_TEXT_HTML, CoeiroInk, Processor, Result, SharedState, Voicevox, DEFAULT_COEIROINK_API_URL, DEFAULT_VOICEVOX_API_URL,
+};
+use actix_web::{get, web, HttpResponse, Responder};
 
 const CONTENT_HEAD: &str = r#"<!DOCTYPE html>
 <meta charset="utf-8">
@@ -11,7 +13,7 @@ const CONTENT_HEAD: &str = r#"<!DOCTYPE html>
 "#;
 
 #[get("/status")]
-async fn get() -> Result<impl Responder> {
+async fn get(state: web::Data<SharedState>) -> Result<impl Responder> {
  log::trace!("/status");
 
  let mut content = CONTENT_HEAD.to_string();
@@ -26,6 +28,10 @@ async fn get() -> Result<impl Responder> {
   content.push_str("</ul>\n<hr>\n");
  }
 
+ content.push_str(&make_processors_section(state.get_ref()).await);
+ content.push_str(&make_audio_queue_section(state.get_ref()).await);
+ content.push_str(&make_audio_devices_section(state.get_ref()).await);
+
  match make_os_tts_section().await {
   Ok(section) => content.push_str(&section),
   Err(e) => {
@@ -34,13 +40,8 @@ async fn get() -> Result<impl Responder> {
   },
  }
 
- match make_coeiroink_section().await {
-  Ok(section) => content.push_str(&section),
-  Err(e) => {
-   log::error!("CoeiroInk の情報を取得できませんでした。: {}", e);
-   content.push_str("<section><h2>OS-TTS</h2><p>CoriroInk の情報を取得できませんでした。</p></section>");
-  },
- }
+ content.push_str(&make_coeiroink_section(state.get_ref()).await);
+ content.push_str(&make_voicevox_section(state.get_ref()).await);
 
  Ok(HttpResponse::Ok().content_type(CONTENT_TYPE_TEXT_HTML).body(content))
 }
@@ -54,6 +55,14 @@ fn make_section(title: &str, content: &str) -> String {
  section
 }
 
+/// 設定ファイルなどから来た文字列を HTML に埋め込めるようにします。
+fn escape_html(s: &str) -> String {
+ s.replace('&', "&amp;")
+  .replace('<', "&lt;")
+  .replace('>', "&gt;")
+  .replace('"', "&quot;")
+}
+
 fn make_tr_td(vs: Vec<String>) -> String {
  let mut tr = String::new();
  tr.push_str("<tr>");
@@ -74,6 +83,118 @@ fn make_tr_th(vs: Vec<String>) -> String {
  tr
 }
 
+async fn make_processors_section(state: &SharedState) -> String {
+ let mut section_content = "".to_string();
+
+ let mut trs = vec![];
+ for p in state.read().await.processors.iter() {
+  let conf = p.conf();
+  let conf = conf.read().await;
+  let is_enabled = if conf.is_enabled {
+   "有効".to_string()
+  } else {
+   "<span style=\"color: gray\">無効</span>".to_string()
+  };
+  trs.push(make_tr_td(vec![
+   escape_html(&conf.get_id()),
+   p.feature().to_string(),
+   escape_html(&conf.group.join(", ")),
+   escape_html(conf.channel_from.as_deref().unwrap_or_default()),
+   escape_html(conf.channel_to.as_deref().unwrap_or_default()),
+   is_enabled,
+  ]));
+ }
+
+ section_content.push_str("<table>\n");
+ const THS: [&str; 6] = ["ID", "Feature", "Group", "Channel From", "Channel To", "Enabled"];
+ section_content.push_str(make_tr_th(THS.iter().map(|s| s.to_string()).collect()).as_str());
+ section_content.push_str(trs.join("\n").as_str());
+ section_content.push_str("</table>\n");
+
+ make_section("Processors", section_content.as_str())
+}
+
+async fn make_audio_queue_section(state: &SharedState) -> String {
+ let audio_queues = state.read().await.audio_queues.clone();
+ let status = audio_queues.status().await;
+
+ let mut section_content = format!("<p>Volume: {}%</p>\n", status.volume.round());
+
+ let trs = status
+  .playing
+  .iter()
+  .map(|e| (e, "再生中"))
+  .chain(status.queued.iter().map(|e| (e, "再生待ち")))
+  .map(|(e, s)| {
+   make_tr_td(vec![
+    e.id.to_string(),
+    s.to_string(),
+    e.processor.clone(),
+    e.device.clone().unwrap_or_else(|| "(default)".to_string()),
+    e.duration_in_ms.map(|d| format!("{:.1}s", d as f64 / 1000.0)).unwrap_or_default(),
+    escape_html(&e.content),
+   ])
+  })
+  .collect::<Vec<_>>();
+
+ section_content.push_str("<table>\n");
+ const THS: [&str; 6] = ["ID", "State", "Processor", "Device", "Duration", "Content"];
+ section_content.push_str(make_tr_th(THS.iter().map(|s| s.to_string()).collect()).as_str());
+ section_content.push_str(trs.join("\n").as_str());
+ section_content.push_str("</table>\n");
+
+ make_section("Audio Queue", section_content.as_str())
+}
+
+async fn make_audio_devices_section(state: &SharedState) -> String {
+ let audio_queues = state.read().await.audio_queues.clone();
+
+ // デバイスの列挙は OS の API を待つため非同期のスレッドを止めないよう別スレッドで
+ let devices =
+  tokio::task::spawn_blocking(|| crate::audio::output_device_names().map(|names| (names, crate::audio::default_output_device_name())))
+   .await;
+ let (names, default) = match devices {
+  Ok(Ok(devices)) => devices,
+  Ok(Err(e)) => {
+   log::error!("音声出力デバイスの一覧を取得できませんでした。: {}", e);
+   return make_section("Audio Devices", "<p>音声出力デバイスの一覧を取得できませんでした。</p>");
+  },
+  Err(e) => {
+   log::error!("音声出力デバイスの一覧を取得する処理が異常終了しました。: {:?}", e);
+   return make_section("Audio Devices", "<p>音声出力デバイスの一覧を取得できませんでした。</p>");
+  },
+ };
+
+ // 設定された名前は一部だけの場合もあるため、再生キューを開いた時と同じ規則で実際のデバイスの名前にする
+ let in_use = audio_queues
+  .device_names()
+  .await
+  .iter()
+  .filter_map(|name| crate::audio::resolve_device_name(&names, name).cloned())
+  .collect::<Vec<_>>();
+
+ let trs = names
+  .into_iter()
+  .map(|name| {
+   make_tr_td(vec![
+    name.clone(),
+    if default.as_ref() == Some(&name) { "✔" } else { "" }.to_string(),
+    if in_use.contains(&name) { "✔" } else { "" }.to_string(),
+   ])
+  })
+  .collect::<Vec<_>>();
+
+ let mut section_content =
+  "<p>Processor の audio_device や audio_sinks の device にはここに表示されたデバイスの名前を設定します。</p>\n".to_string();
+ section_content.push_str("<table>\n");
+ const THS: [&str; 3] = ["Name", "Default", "In Use"];
+ section_content.push_str(make_tr_th(THS.iter().map(|s| s.to_string()).collect()).as_str());
+ section_content.push_str(trs.join("\n").as_str());
+ section_content.push_str("</table>\n");
+
+ make_section("Audio Devices", section_content.as_str())
+}
+
 async fn make_os_tts_section() -> Result<String> {
  let mut section_content = "".to_string();
 
@@ -98,45 +219,75 @@ async fn make_os_tts_section() -> Result<String> {
  Ok(make_section("《OS-TTS》", section_content.as_str()))
 }
 
-async fn make_coeiroink_section() -> Result<String> {
+async fn make_coeiroink_section(state: &SharedState) -> String {
+ // coeiroink の Processor の api_url のベースの URL 群。無ければ CoeiroInk の既定の URL
+ let mut base_urls = vec![];
+ for p in state.read().await.processors.iter() {
+  if p.feature() != CoeiroInk::FEATURE {
+   continue;
+  }
+  if let Some(api_url) = p.conf().read().await.api_url.clone() {
+   let base_url = CoeiroInk::base_url(&api_url);
+   if !base_urls.contains(&base_url) {
+    base_urls.push(base_url);
+   }
+  }
+ }
+ if base_urls.is_empty() {
+  base_urls.push(CoeiroInk::base_url(DEFAULT_COEIROINK_API_URL));
+ }
+
  let mut section_content = "".to_string();
 
  let mut trs = vec![];
- let speakers = crate::processor::CoeiroInk::get_speakers().await?;
- for speaker in speakers {
-  let img = if let Some(v) = speaker.base64Portrait {
-   format!("<img src=\"data:image/png;base64,{}\" style=\"max-height:8em\">", v)
-  } else {
-   "".to_string()
+ for base_url in base_urls {
+  let speakers = match CoeiroInk::get_speakers(&base_url).await {
+   Ok(speakers) => speakers,
+   Err(e) => {
+    log::error!("CoeiroInk の情報を取得できませんでした。: {} {}", base_url, e);
+    trs.push(make_tr_td(vec![
+     base_url.clone(),
+     "<span style=\"color: gray\">情報を取得できませんでした。</span>".to_string(),
+    ]));
+    continue;
+   },
   };
-  let name = speaker.speakerName;
-  let uuid = speaker.speakerUuid;
-  for style in speaker.styles {
-   let style_name = style.styleName;
-   let style_id = style.styleId.to_string();
-   let text = format!(
-    "バーチャルアバターコネクトからこんにちは！コエイロインク{}の{}スタイルのテストです。",
-    &name, &style_name
-   )
-   .to_string();
-   let play_button = format!(
-    "<div style=\"width: 10em\"><button onclick=\"test_coeiroink(this, '{}',{},'{}')\">Play</button></div>",
-    uuid, style_id, text
-   );
-   trs.push(make_tr_td(vec![
-    img.clone(),
-    name.clone(),
-    uuid.clone(),
-    style_name,
-    style_id,
-    play_button,
-   ]));
+  for speaker in speakers {
+   let img = if let Some(v) = speaker.base64Portrait {
+    format!("<img src=\"data:image/png;base64,{}\" style=\"max-height:8em\">", v)
+   } else {
+    "".to_string()
+   };
+   let name = speaker.speakerName;
+   let uuid = speaker.speakerUuid;
+   for style in speaker.styles {
+    let style_name = style.styleName;
+    let style_id = style.styleId.to_string();
+    let text = format!(
+     "バーチャルアバターコネクトからこんにちは！コエイロインク{}の{}スタイルのテストです。",
+     &name, &style_name
+    )
+    .to_string();
+    let play_button = format!(
+     "<div style=\"width: 10em\"><button onclick=\"test_coeiroink(this, '{}','{}',{},'{}')\">Play</button></div>",
+     base_url, uuid, style_id, text
+    );
+    trs.push(make_tr_td(vec![
+     base_url.clone(),
+     img.clone(),
+     name.clone(),
+     uuid.clone(),
+     style_name,
+     style_id,
+     play_button,
+    ]));
+   }
   }
  }
 
  section_content.push_str(
   r#"<script>
-async function test_coeiroink(element, uuid, style_id, text) {
+async function test_coeiroink(element, base_url, uuid, style_id, text) {
  try
  {
   element.innerText = 'Loading...'
@@ -144,7 +295,7 @@ async function test_coeiroink(element, uuid, style_id, text) {
   let method = 'POST'
   let headers = { 'Content-Type': 'application/json' }
   let body = JSON.stringify({ speakerUuid: uuid, styleId: style_id, text, speedScale: 1 })
-  let url = 'http://127.0.0.1:50032/v1/predict'
+  let url = `${base_url}/v1/predict`
   let wav = await fetch(url, { method, headers, body })
   let data = await wav.arrayBuffer()
   let ac = new AudioContext()
@@ -169,10 +320,109 @@ async function test_coeiroink(element, uuid, style_id, text) {
 </script>"#,
  );
  section_content.push_str("<table>\n");
- const THS: [&str; 6] = ["Portrait", "Name", "UUID", "Style Name", "Style ID", "Test"];
+ const THS: [&str; 7] = ["API URL", "Portrait", "Name", "UUID", "Style Name", "Style ID", "Test"];
+ section_content.push_str(make_tr_th(THS.iter().map(|s| s.to_string()).collect()).as_str());
+ section_content.push_str(trs.join("\n").as_str());
+ section_content.push_str("</table>\n");
+
+ make_section("《CoeiroInk》", section_content.as_str())
+}
+
+async fn make_voicevox_section(state: &SharedState) -> String {
+ // voicevox の Processor の api_url 群。無ければ VOICEVOX の既定の URL
+ let mut api_urls = vec![];
+ for p in state.read().await.processors.iter() {
+  if p.feature() != Voicevox::FEATURE {
+   continue;
+  }
+  if let Some(api_url) = p.conf().read().await.api_url.clone() {
+   if !api_urls.contains(&api_url) {
+    api_urls.push(api_url);
+   }
+  }
+ }
+ if api_urls.is_empty() {
+  api_urls.push(DEFAULT_VOICEVOX_API_URL.to_string());
+ }
+
+ let mut trs = vec![];
+ for api_url in api_urls {
+  let speakers = match Voicevox::get_speakers(&api_url).await {
+   Ok(speakers) => speakers,
+   Err(e) => {
+    log::error!("VOICEVOX の情報を取得できませんでした。: {} {}", api_url, e);
+    trs.push(make_tr_td(vec![
+     escape_html(&api_url),
+     "<span style=\"color: gray\">情報を取得できませんでした。</span>".to_string(),
+    ]));
+    continue;
+   },
+  };
+  for speaker in speakers {
+   for style in speaker.styles {
+    let text = format!(
+     "バーチャルアバターコネクトからこんにちは！{}の{}スタイルのテストです。",
+     &speaker.name, &style.name
+    );
+    // 話者やスタイルの名前に ' や " が含まれても壊れないよう、 JS の文字列ではなく data-* 属性で渡す
+    let play_button = format!(
+     "<div style=\"width: 10em\"><button data-api-url=\"{}\" data-style-id=\"{}\" data-text=\"{}\" onclick=\"test_voicevox(this, this.dataset.apiUrl, this.dataset.styleId, this.dataset.text)\">Play</button></div>",
+     escape_html(&api_url),
+     style.id,
+     escape_html(&text)
+    );
+    trs.push(make_tr_td(vec![
+     escape_html(&api_url),
+     escape_html(&speaker.name),
+     escape_html(&speaker.speaker_uuid),
+     escape_html(&style.name),
+     style.id.to_string(),
+     play_button,
+    ]));
+   }
+  }
+ }
+
+ let mut section_content = "".to_string();
+ section_content.push_str(
+  r#"<script>
+async function test_voicevox(element, api_url, style_id, text) {
+ try
+ {
+  element.innerText = 'Loading...'
+  element.disabled = true
+  let query = await fetch(`${api_url}/audio_query?speaker=${style_id}&text=${encodeURIComponent(text)}`, { method: 'POST' })
+  let method = 'POST'
+  let headers = { 'Content-Type': 'application/json' }
+  let body = JSON.stringify(await query.json())
+  let wav = await fetch(`${api_url}/synthesis?speaker=${style_id}`, { method, headers, body })
+  let data = await wav.arrayBuffer()
+  let ac = new AudioContext()
+  ac.decodeAudioData(data, buffer => {
+   let source = ac.createBufferSource()
+   source.buffer = buffer
+   source.connect(ac.destination)
+   source.onended = () => {
+    element.innerText = 'Play'
+    element.disabled = false
+   }
+   source.start()
+  })
+ }
+ catch (e)
+ {
+  console.error(e)
+  element.innerText = 'Play'
+  element.disabled = false
+ }
+}
+</script>"#,
+ );
+ section_content.push_str("<table>\n");
+ const THS: [&str; 6] = ["API URL", "Name", "UUID", "Style Name", "Style ID", "Test"];
  section_content.push_str(make_tr_th(THS.iter().map(|s| s.to_string()).collect()).as_str());
  section_content.push_str(trs.join("\n").as_str());
  section_content.push_str("</table>\n");
 
- Ok(make_section("《CoeiroInk》", section_content.as_str()))
+ make_section("《VOICEVOX》", section_content.as_str())
 }
@@ -1,14 +1,15 @@
-use crate::{Arc, ChannelData, ChannelDatum, RwLock, SharedChannelData, SharedState};
+use crate::{
+ state::ChannelEventRecvError, Arc, AudioCommand, AudioQueueStatus, AudioQueues, ChannelData, ChannelDatum, ChannelDatumMetadata,
+ ChannelEventBus, SharedChannelData, SharedState,
+};
 
-use actix::{Actor, ActorContext, AsyncContext, Handler, Message, StreamHandler};
+use actix::{Actor, ActorContext, AsyncContext, Handler, Message, SpawnHandle, StreamHandler};
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashSet, VecDeque};
-use tokio::time::Duration;
-
-const DEFAULT_PUSH_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsServerPayloadChannelDatum {
@@ -18,6 +19,8 @@ pub struct WsServerPayloadChannelDatum {
  pub flags: HashSet<String>,
  pub id: Option<u64>,
  pub datetime: Option<DateTime<Utc>>,
+ #[serde(default)]
+ pub metadata: ChannelDatumMetadata,
 }
 
 impl WsServerPayloadChannelDatum {
@@ -29,11 +32,12 @@ impl WsServerPayloadChannelDatum {
    flags: cd.flags.clone(),
    id: Some(cd.get_id()),
    datetime: Some(cd.get_datetime()),
+   metadata: cd.metadata.clone(),
   }
  }
  /// Client --> Server 入力受信用
  pub fn to_channel_datum(self) -> ChannelDatum {
-  let mut cd = ChannelDatum::new(self.channel, self.content);
+  let mut cd = ChannelDatum::new(self.channel, self.content).with_metadata(self.metadata);
   for f in self.flags.into_iter() {
    cd = cd.with_flag(&f);
   }
@@ -43,18 +47,93 @@ impl WsServerPayloadChannelDatum {
 
 pub type WsServerPayloadChannelData = VecDeque<WsServerPayloadChannelDatum>;
 
+/// Client --> Server 購読設定用
+/// 一度も subscribe していないクライアントには従来どおり全てのチャンネルの内容がプッシュ配信されます。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WsServerPayloadSubscription {
+ /// 購読したいチャンネル名。 * (任意の文字列) と ? (任意の1文字) のワイルドカードを使えます。空の場合は全てのチャンネルが対象です。
+ #[serde(default)]
+ pub channels: Vec<String>,
+ /// ここで指定したフラグを全て持つ内容だけを購読します。(例: ["is_final"])
+ #[serde(default)]
+ pub flags: Vec<String>,
+ /// ここで指定したフラグを1つでも持つ内容は購読しません。
+ #[serde(default)]
+ pub without_flags: Vec<String>,
+ /// true にすると再生キューの状態が変わる度に audio_queue がプッシュ配信されます。チャンネルの購読とは独立しています。
+ #[serde(default)]
+ pub audio_queue: bool,
+}
+
+impl WsServerPayloadSubscription {
+ fn has_channel_conditions(&self) -> bool {
+  !self.channels.is_empty() || !self.flags.is_empty() || !self.without_flags.is_empty()
+ }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsServerPayload {
  pub channel_data: Option<WsServerPayloadChannelData>,
  pub channel_datum: Option<WsServerPayloadChannelDatum>,
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub subscribe: Option<WsServerPayloadSubscription>,
+ /// channels に subscribe 済みのチャンネル名やパターンを指定すると購読を解除します。空の場合は全ての購読を解除します。
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub unsubscribe: Option<WsServerPayloadSubscription>,
+ /// Client --> Server 再生キューの操作用 (例: {"audio":{"command":"skip"}})
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub audio: Option<AudioCommand>,
+ /// Server --> Client 再生キューの状態のプッシュ配信用
+ #[serde(default, skip_serializing_if = "Option::is_none")]
+ pub audio_queue: Option<AudioQueueStatus>,
+}
+
+/// 購読設定をパターンのコンパイル済みの状態で保持したもの
+#[derive(Debug, Clone)]
+struct WsSubscription {
+ channels: Vec<(String, Regex)>,
+ flags: Vec<String>,
+ without_flags: Vec<String>,
+}
+
+impl WsSubscription {
+ fn new(subscription: WsServerPayloadSubscription) -> Result<Self, regex::Error> {
+  let channels = subscription
+   .channels
+   .into_iter()
+   .map(|pattern| wildcard_to_regex(&pattern).map(|regex| (pattern, regex)))
+   .collect::<Result<Vec<_>, _>>()?;
+  Ok(Self {
+   channels,
+   flags: subscription.flags,
+   without_flags: subscription.without_flags,
+  })
+ }
+
+ fn is_match(&self, cd: &ChannelDatum) -> bool {
+  (self.channels.is_empty() || self.channels.iter().any(|(_, regex)| regex.is_match(&cd.channel)))
+   && self.flags.iter().all(|f| cd.has_flag(f))
+   && !self.without_flags.iter().any(|f| cd.has_flag(f))
+ }
+}
+
+/// * と ? のワイルドカードを含むチャンネル名のパターンを正規表現へ変換
+fn wildcard_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+ let pattern = regex::escape(pattern).replace(r"\*", ".*").replace(r"\?", ".");
+ Regex::new(&format!("^{}$", pattern))
 }
 
 #[derive(Clone, Debug)]
 pub struct WebSocketServer {
  pub state: SharedState,
  pub channel_data: SharedChannelData,
- pub last_retrieved_id: Arc<RwLock<u64>>,
+ pub channel_event_bus: ChannelEventBus,
+ pub audio_queues: AudioQueues,
  pub client: Option<actix::Addr<WebSocketServer>>,
+ /// None: 一度も subscribe していない(全て配信) / Some: 何れかの購読設定にマッチした内容だけ配信(空なら何も配信しない)
+ subscriptions: Option<Vec<WsSubscription>>,
+ /// 再生キューの状態をプッシュ配信するタスク
+ audio_queue_subscription: Option<SpawnHandle>,
 }
 
 #[derive(Message)]
@@ -69,13 +148,143 @@ impl Handler<StringMessage> for WebSocketServer {
  }
 }
 
+/// ChannelEventBus から受け取った内容をクライアントの購読設定で絞り込んでから送出するためのメッセージ
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChannelDataMessage(pub Vec<Arc<ChannelDatum>>);
+
+impl Handler<ChannelDataMessage> for WebSocketServer {
+ type Result = ();
+
+ fn handle(&mut self, msg: ChannelDataMessage, ctx: &mut Self::Context) {
+  let data = msg
+   .0
+   .iter()
+   .filter(|cd| self.is_subscribed(cd))
+   .map(|cd| WsServerPayloadChannelDatum::from_channel_datum(cd))
+   .collect::<VecDeque<_>>();
+  let payload = match data.len() {
+   0 => return,
+   1 => WsServerPayload {
+    channel_data: None,
+    channel_datum: data.into_iter().next(),
+    subscribe: None,
+    unsubscribe: None,
+    audio: None,
+    audio_queue: None,
+   },
+   _ => WsServerPayload {
+    channel_data: Some(data),
+    channel_datum: None,
+    subscribe: None,
+    unsubscribe: None,
+    audio: None,
+    audio_queue: None,
+   },
+  };
+  ctx.text(serde_json::to_string(&payload).unwrap());
+ }
+}
+
+/// 再生キューの状態を送出するためのメッセージ
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AudioQueueMessage(pub AudioQueueStatus);
+
+impl Handler<AudioQueueMessage> for WebSocketServer {
+ type Result = ();
+
+ fn handle(&mut self, msg: AudioQueueMessage, ctx: &mut Self::Context) {
+  let payload = WsServerPayload {
+   channel_data: None,
+   channel_datum: None,
+   subscribe: None,
+   unsubscribe: None,
+   audio: None,
+   audio_queue: Some(msg.0),
+  };
+  ctx.text(serde_json::to_string(&payload).unwrap());
+ }
+}
+
 impl WebSocketServer {
  pub async fn new(state: &SharedState) -> Self {
+  let (channel_data, channel_event_bus, audio_queues) = {
+   let state = state.read().await;
+   (
+    state.channel_data.clone(),
+    state.channel_event_bus.clone(),
+    state.audio_queues.clone(),
+   )
+  };
   Self {
    state: state.clone(),
-   channel_data: state.read().await.channel_data.clone(),
-   last_retrieved_id: Arc::new(RwLock::new(ChannelDatum::get_last_id())),
+   channel_data,
+   channel_event_bus,
+   audio_queues,
    client: None,
+   subscriptions: None,
+   audio_queue_subscription: None,
+  }
+ }
+
+ /// 再生キューの状態を購読し、購読した時点の状態とその後の変化をプッシュ配信します。
+ fn subscribe_audio_queue(&mut self, ctx: &mut <Self as Actor>::Context) {
+  if self.audio_queue_subscription.is_some() {
+   return;
+  }
+  log::debug!("WebSocket クライアントが再生キューの状態を購読します。");
+  let mut receiver = self.audio_queues.subscribe();
+  let addr = ctx.address();
+  let handle = ctx.spawn(actix::fut::wrap_future(async move {
+   loop {
+    let status = receiver.borrow_and_update().clone();
+    if addr.send(AudioQueueMessage(status)).await.is_err() || receiver.changed().await.is_err() {
+     break;
+    }
+   }
+  }));
+  self.audio_queue_subscription = Some(handle);
+ }
+
+ fn unsubscribe_audio_queue(&mut self, ctx: &mut <Self as Actor>::Context) {
+  if let Some(handle) = self.audio_queue_subscription.take() {
+   log::debug!("WebSocket クライアントが再生キューの状態の購読を解除します。");
+   ctx.cancel_future(handle);
+  }
+ }
+
+ fn is_subscribed(&self, cd: &ChannelDatum) -> bool {
+  match &self.subscriptions {
+   None => true,
+   Some(subscriptions) => subscriptions.iter().any(|s| s.is_match(cd)),
+  }
+ }
+
+ fn subscribe(&mut self, subscription: WsServerPayloadSubscription) {
+  log::debug!("WebSocket クライアントが購読を追加します: {:?}", subscription);
+  match WsSubscription::new(subscription) {
+   Ok(subscription) => self.subscriptions.get_or_insert_with(Vec::new).push(subscription),
+   Err(e) => log::error!("WebSocket の購読設定のチャンネルのパターンが不正です: {:?}", e),
+  }
+ }
+
+ fn unsubscribe(&mut self, subscription: WsServerPayloadSubscription) {
+  log::debug!("WebSocket クライアントが購読を解除します: {:?}", subscription);
+  // 全ての購読の解除は何も配信しない状態にする(再接続した api.js も購読設定が空ならこれを送る)
+  if subscription.channels.is_empty() {
+   self.subscriptions = Some(vec![]);
+   return;
+  }
+  // 一度も購読していない場合は解除するものが無いので全て配信のまま
+  if let Some(subscriptions) = self.subscriptions.as_mut() {
+   // チャンネルのパターンが全て解除された購読設定は取り除く(残すと全チャンネルが対象になってしまう)
+   // 元から全チャンネル対象の購読設定は残す。購読設定が無くなっても何も配信しない状態のまま
+   subscriptions.retain_mut(|s| {
+    let had_channels = !s.channels.is_empty();
+    s.channels.retain(|(pattern, _)| !subscription.channels.contains(pattern));
+    !(had_channels && s.channels.is_empty())
+   });
   }
  }
 }
@@ -86,34 +295,42 @@ impl Actor for WebSocketServer {
  fn started(&mut self, ctx: &mut Self::Context) {
   log::debug!("WebSocket が開始されました。");
   let channel_data = self.channel_data.clone();
-  let last_retrieved_id = self.last_retrieved_id.clone();
+  let mut receiver = self.channel_event_bus.subscribe();
   let addr = ctx.address();
   ctx.spawn(actix::fut::wrap_future(async move {
    // Server --> Client プッシュ配信
+   // 接続した時点以降に追加/更新された ChannelDatum を ChannelEventBus から受け取り次第送出する
+   let mut last_sent_id = ChannelDatum::get_last_id();
    loop {
-    let channel_data = channel_data.read().await;
-    let mut last_retrieved_id = last_retrieved_id.write().await;
-    let last_retrieved_id_read_only = *last_retrieved_id;
-    let data = channel_data
-     .iter()
-     .filter_map(|cd| {
-      if cd.get_id() > last_retrieved_id_read_only {
-       Some(WsServerPayloadChannelDatum::from_channel_datum(&cd))
-      } else {
-       None
-      }
-     })
-     .collect::<VecDeque<_>>();
-    if data.len() > 0 {
-     *last_retrieved_id = data.back().map(|cd| cd.id.unwrap()).unwrap();
-     let json = serde_json::to_string(&WsServerPayload {
-      channel_data: Some(data),
-      channel_datum: None,
-     })
-     .unwrap();
-     addr.send(StringMessage(json)).await.unwrap();
+    let data = match receiver.recv().await {
+     Ok(cd) => vec![cd],
+     Err(ChannelEventRecvError::Lagged(skipped)) => {
+      // 受信が追いつかず取りこぼした場合は channel_data から未送信分をまとめて拾い直す
+      log::warn!(
+       "WebSocket への配信が追いつかず {} 件の ChannelDatum を取りこぼしたため channel_data から再取得します。",
+       skipped
+      );
+      let channel_data = channel_data.read().await;
+      channel_data
+       .iter()
+       .filter(|cd| cd.get_id() > last_sent_id)
+       .map(|cd| Arc::new(cd.clone()))
+       .collect::<Vec<_>>()
+     },
+     Err(ChannelEventRecvError::Closed) => {
+      log::debug!("ChannelEventBus が閉じられたため WebSocket への配信を終了します。");
+      break;
+     },
+    };
+    if data.is_empty() {
+     continue;
+    }
+    last_sent_id = data.iter().map(|cd| cd.get_id()).max().unwrap_or(last_sent_id).max(last_sent_id);
+    // 購読設定による絞り込みはアクター側で行う
+    if addr.send(ChannelDataMessage(data)).await.is_err() {
+     log::debug!("WebSocket の送信先が既に停止しているため配信を終了します。");
+     break;
     }
-    tokio::time::sleep(DEFAULT_PUSH_INTERVAL).await;
    }
   }));
  }
@@ -130,6 +347,28 @@ impl StreamHandler<actix_web::Result<ws::Message, ws::ProtocolError>> for WebSoc
     log::trace!("WebSocket がテキストを受信しました。 {:?}", text);
 
     if let Ok(ws_server_payload) = serde_json::from_str::<WsServerPayload>(&text) {
+     if let Some(unsubscription) = ws_server_payload.unsubscribe {
+      if unsubscription.audio_queue {
+       self.unsubscribe_audio_queue(ctx);
+      }
+      if !unsubscription.audio_queue || unsubscription.has_channel_conditions() {
+       self.unsubscribe(unsubscription);
+      }
+     }
+     if let Some(subscription) = ws_server_payload.subscribe {
+      if subscription.audio_queue {
+       self.subscribe_audio_queue(ctx);
+      }
+      if !subscription.audio_queue || subscription.has_channel_conditions() {
+       self.subscribe(subscription);
+      }
+     }
+     if let Some(command) = ws_server_payload.audio {
+      let audio_queues = self.audio_queues.clone();
+      tokio::spawn(async move {
+       audio_queues.execute(&command).await;
+      });
+     }
      if let Some(ws_channel_datum) = ws_server_payload.channel_datum {
       let channel_datum = ws_channel_datum.to_channel_datum();
       log::trace!("channel_datum = {:?}", channel_datum);
@@ -174,3 +413,61 @@ pub async fn websocket(
  let s = WebSocketServer::new(&state).await;
  ws::start(s, &r, stream)
 }
+
+#[cfg(test)]
+mod tests {
+ use super::*;
+ use crate::{AudioBackend, AudioOutput, Conf, ProcessorRegistry, State};
+
+ async fn new_server() -> WebSocketServer {
+  let conf: Conf = toml::from_str("").unwrap();
+  let audio_output = AudioOutput::open(&AudioBackend::Null).unwrap();
+  let state = State::new(&conf, audio_output.audio_sink.clone(), ProcessorRegistry::new())
+   .await
+   .unwrap();
+  WebSocketServer::new(&state).await
+ }
+
+ fn subscription(channels: &[&str]) -> WsServerPayloadSubscription {
+  WsServerPayloadSubscription {
+   channels: channels.iter().map(|c| c.to_string()).collect(),
+   ..Default::default()
+  }
+ }
+
+ fn datum(channel: &str) -> ChannelDatum {
+  ChannelDatum::new(channel.to_string(), "hello".to_string())
+ }
+
+ #[tokio::test]
+ async fn unsubscribing_last_channel_delivers_nothing() {
+  let mut server = new_server().await;
+  assert!(server.is_subscribed(&datum("ai")));
+
+  server.subscribe(subscription(&["ai"]));
+  assert!(server.is_subscribed(&datum("ai")));
+  assert!(!server.is_subscribed(&datum("user")));
+
+  server.unsubscribe(subscription(&["ai"]));
+  assert!(!server.is_subscribed(&datum("ai")));
+  assert!(!server.is_subscribed(&datum("user")));
+ }
+
+ #[tokio::test]
+ async fn unsubscribing_all_delivers_nothing() {
+  let mut server = new_server().await;
+  server.unsubscribe(subscription(&[]));
+  assert!(!server.is_subscribed(&datum("ai")));
+ }
+
+ #[tokio::test]
+ async fn subscription_with_flags_is_removed_with_its_channels() {
+  let mut server = new_server().await;
+  server.subscribe(WsServerPayloadSubscription {
+   flags: vec![ChannelDatum::FLAG_IS_FINAL.to_string()],
+   ..subscription(&["ai"])
+  });
+  server.unsubscribe(subscription(&["ai"]));
+  assert!(!server.is_subscribed(&datum("user").with_flag(ChannelDatum::FLAG_IS_FINAL)));
+ }
+}